@@ -2,6 +2,19 @@ CREATE TABLE IF NOT EXISTS categories (num INT UNIQUE NOT NULL , id varchar(255)
 CREATE TABLE IF NOT EXISTS users (id varchar(255) NOT NULL, name TEXT, authsource JSON, PRIMARY KEY (id));
 CREATE TABLE IF NOT EXISTS memes (id SERIAL, filename varchar(255) NOT NULL, userid varchar(255) NOT NULL, category varchar(255), timestamp TIMESTAMP, ip varchar(255), cid varchar(255) NOT NULL, PRIMARY KEY (id), FOREIGN KEY (category) REFERENCES categories(id), FOREIGN KEY (userid) REFERENCES users(id));
 CREATE TABLE IF NOT EXISTS token (uid varchar(255) UNIQUE NOT NULL, token varchar(255), FOREIGN KEY (uid) REFERENCES users(id));
+ALTER TABLE memes ADD COLUMN IF NOT EXISTS size BIGINT;
+CREATE INDEX IF NOT EXISTS memes_cid ON memes (cid);
+CREATE TABLE IF NOT EXISTS outbox (id SERIAL, memeid INT NOT NULL, action varchar(255) NOT NULL, attempts INT NOT NULL DEFAULT 0, error TEXT, created TIMESTAMP NOT NULL DEFAULT NOW(), next_attempt TIMESTAMP NOT NULL DEFAULT NOW(), done TIMESTAMP, failed TIMESTAMP, PRIMARY KEY (id), FOREIGN KEY (memeid) REFERENCES memes(id) ON DELETE CASCADE);
+CREATE TABLE IF NOT EXISTS consistency_check (id SERIAL, started TIMESTAMP NOT NULL DEFAULT NOW(), finished TIMESTAMP, last_meme INT NOT NULL DEFAULT 0, checked INT NOT NULL DEFAULT 0, PRIMARY KEY (id));
+CREATE TABLE IF NOT EXISTS consistency_issues (checkid INT NOT NULL, memeid INT NOT NULL, issue varchar(255) NOT NULL, repaired BOOLEAN NOT NULL DEFAULT FALSE, PRIMARY KEY (checkid, memeid, issue), FOREIGN KEY (checkid) REFERENCES consistency_check(id), FOREIGN KEY (memeid) REFERENCES memes(id));
+CREATE TABLE IF NOT EXISTS orphan_pins (cid varchar(255) NOT NULL, first_seen TIMESTAMP NOT NULL DEFAULT NOW(), PRIMARY KEY (cid));
+CREATE TABLE IF NOT EXISTS appservice_transactions (txn_id varchar(255) NOT NULL, received TIMESTAMP NOT NULL DEFAULT NOW(), PRIMARY KEY (txn_id));
+CREATE TABLE IF NOT EXISTS matrix_media (cid varchar(255) NOT NULL, mxc varchar(255) NOT NULL, PRIMARY KEY (cid));
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar varchar(255);
+CREATE TABLE IF NOT EXISTS matrix_profiles (userid varchar(255) NOT NULL, name TEXT, avatar varchar(255), PRIMARY KEY (userid), FOREIGN KEY (userid) REFERENCES users(id));
+CREATE TABLE IF NOT EXISTS added_pins (cid varchar(255) NOT NULL, added TIMESTAMP NOT NULL DEFAULT NOW(), PRIMARY KEY (cid));
+INSERT INTO added_pins (cid) SELECT DISTINCT cid FROM memes ON CONFLICT DO NOTHING;
+CREATE TABLE IF NOT EXISTS thumbnails (cid varchar(255) NOT NULL, thumbnail varchar(255) NOT NULL, filename varchar(255) NOT NULL, PRIMARY KEY (cid));
 CREATE OR REPLACE FUNCTION UNIX_TIMESTAMP(ts TIMESTAMP) RETURNS INT AS $$
         BEGIN
                 RETURN extract(epoch FROM ts)::integer;
@@ -0,0 +1,300 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+    sync::watch,
+};
+
+use crate::{config::CacheConfig, error::ServiceError, JMServiceInner};
+
+/// A content-addressed cache of IPFS files on the local disk. Every file is
+/// stored under its CID, and the least recently used files are removed once
+/// the total size exceeds the configured limit.
+pub struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    index: Mutex<CacheIndex>,
+    fetches: Mutex<HashMap<String, watch::Receiver<()>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    lru: BTreeMap<u64, String>,
+    size: u64,
+    tick: u64,
+}
+
+struct CacheEntry {
+    size: u64,
+    tick: u64,
+}
+
+pub struct CachedFile {
+    pub file: File,
+    pub size: u64,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub files: usize,
+    pub size: u64,
+    pub max_size: u64,
+}
+
+enum Fetch<'a> {
+    Leader(FetchGuard<'a>),
+    Follower(watch::Receiver<()>),
+}
+
+/// Held by the request fetching a file into the cache. Dropping it, even when
+/// the request is cancelled, ends the fetch for the waiting requests, so one
+/// of them can retry, and removes the partially written file.
+struct FetchGuard<'a> {
+    cache: &'a DiskCache,
+    cid: String,
+    tmp: Option<PathBuf>,
+    _tx: watch::Sender<()>,
+}
+
+impl DiskCache {
+    /// Opens the cache directory, indexing the files left from earlier runs
+    /// in the order they were last modified. Only called on startup, so it
+    /// may block.
+    pub fn new(config: &CacheConfig) -> std::io::Result<Self> {
+        let tmp = config.dir.join("tmp");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)?;
+        }
+        fs::create_dir_all(&tmp)?;
+
+        let mut files = vec![];
+        for entry in fs::read_dir(&config.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if meta.is_file() {
+                let cid = entry.file_name().to_string_lossy().into_owned();
+                files.push((meta.modified()?, cid, meta.len()));
+            }
+        }
+        files.sort();
+
+        let mut index = CacheIndex::default();
+        for (_, cid, size) in files {
+            index.insert(cid, size);
+        }
+        while index.size > config.max_size && index.entries.len() > 1 {
+            if let Some(cid) = index.pop_oldest() {
+                fs::remove_file(config.dir.join(cid))?;
+            }
+        }
+
+        Ok(Self {
+            dir: config.dir.clone(),
+            max_size: config.max_size,
+            index: Mutex::new(index),
+            fetches: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let index = self.index.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            files: index.entries.len(),
+            size: index.size,
+            max_size: self.max_size,
+        }
+    }
+
+    fn path(&self, cid: &str) -> PathBuf {
+        self.dir.join(cid)
+    }
+
+    async fn open(&self, cid: &str) -> Result<Option<CachedFile>, ServiceError> {
+        let size = match self.index.lock().unwrap().touch(cid) {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+        match File::open(self.path(cid)).await {
+            Ok(file) => Ok(Some(CachedFile { file, size })),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                self.index.lock().unwrap().remove(cid);
+                Ok(None)
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn join_fetch(&self, cid: &str) -> Fetch<'_> {
+        let mut fetches = self.fetches.lock().unwrap();
+        match fetches.get(cid) {
+            Some(rx) => Fetch::Follower(rx.clone()),
+            None => {
+                let (tx, rx) = watch::channel(());
+                fetches.insert(cid.to_string(), rx);
+                Fetch::Leader(FetchGuard {
+                    cache: self,
+                    cid: cid.to_string(),
+                    tmp: None,
+                    _tx: tx,
+                })
+            },
+        }
+    }
+
+    async fn add(&self, cid: &str, tmp: &Path, size: u64) -> std::io::Result<()> {
+        tokio::fs::rename(tmp, self.path(cid)).await?;
+        self.index.lock().unwrap().insert(cid.to_string(), size);
+        self.evict().await
+    }
+
+    async fn evict(&self) -> std::io::Result<()> {
+        loop {
+            let cid = {
+                let mut index = self.index.lock().unwrap();
+                if index.size <= self.max_size || index.entries.len() <= 1 {
+                    return Ok(());
+                }
+                index.pop_oldest()
+            };
+            if let Some(cid) = cid {
+                match tokio::fs::remove_file(self.path(&cid)).await {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl CacheIndex {
+    fn insert(&mut self, cid: String, size: u64) {
+        self.remove(&cid);
+        self.tick += 1;
+        self.lru.insert(self.tick, cid.clone());
+        self.entries.insert(
+            cid,
+            CacheEntry {
+                size,
+                tick: self.tick,
+            },
+        );
+        self.size += size;
+    }
+
+    fn touch(&mut self, cid: &str) -> Option<u64> {
+        let entry = self.entries.get_mut(cid)?;
+        self.tick += 1;
+        self.lru.remove(&entry.tick);
+        self.lru.insert(self.tick, cid.to_string());
+        entry.tick = self.tick;
+        Some(entry.size)
+    }
+
+    fn remove(&mut self, cid: &str) {
+        if let Some(entry) = self.entries.remove(cid) {
+            self.lru.remove(&entry.tick);
+            self.size -= entry.size;
+        }
+    }
+
+    fn pop_oldest(&mut self) -> Option<String> {
+        let cid = self.lru.values().next()?.clone();
+        self.remove(&cid);
+        Some(cid)
+    }
+}
+
+impl Drop for FetchGuard<'_> {
+    fn drop(&mut self) {
+        // Removed before the sender is dropped, so woken requests don't join
+        // this fetch again
+        self.cache.fetches.lock().unwrap().remove(&self.cid);
+        if let Some(tmp) = self.tmp.take() {
+            tokio::spawn(async move {
+                let _ = tokio::fs::remove_file(tmp).await;
+            });
+        }
+    }
+}
+
+impl CachedFile {
+    pub async fn seek(&mut self, offset: u64) -> Result<(), ServiceError> {
+        self.file.seek(SeekFrom::Start(offset)).await?;
+        Ok(())
+    }
+}
+
+impl JMServiceInner {
+    /// Returns the file with the given CID from the disk cache, fetching it
+    /// from IPFS first if needed. Concurrent requests for the same CID wait
+    /// for a single fetch. Returns `None` if the cache is disabled.
+    pub async fn cached_cat(&self, cid: &str) -> Result<Option<CachedFile>, ServiceError> {
+        let cache = match &self.cache {
+            Some(cache) if !cid.is_empty() && cid.chars().all(|c| c.is_ascii_alphanumeric()) => {
+                cache
+            },
+            _ => return Ok(None),
+        };
+        if let Some(file) = cache.open(cid).await? {
+            cache.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Some(file));
+        }
+        cache.misses.fetch_add(1, Ordering::Relaxed);
+
+        loop {
+            match cache.join_fetch(cid) {
+                Fetch::Leader(mut guard) => self.fetch_into_cache(&mut guard).await?,
+                Fetch::Follower(mut rx) => {
+                    // The sender is dropped once the fetch is finished.
+                    while rx.changed().await.is_ok() {}
+                },
+            }
+            if let Some(file) = cache.open(cid).await? {
+                return Ok(Some(file));
+            }
+        }
+    }
+
+    async fn fetch_into_cache(&self, guard: &mut FetchGuard<'_>) -> Result<(), ServiceError> {
+        let cache = guard.cache;
+        let mut content = self.storage.cat(&guard.cid).await?;
+        let tmp = cache
+            .dir
+            .join("tmp")
+            .join(format!("{}.{}", guard.cid, rand::random::<u32>()));
+        // Removed by the guard unless it is moved into the cache
+        guard.tmp = Some(tmp.clone());
+        let mut file = File::create(&tmp).await?;
+        let mut size = 0;
+        while let Some(chunk) = content.stream.try_next().await? {
+            file.write_all(&chunk).await?;
+            size += chunk.len() as u64;
+        }
+        file.flush().await?;
+        drop(file);
+        cache.add(&guard.cid, &tmp, size).await?;
+        guard.tmp = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::ops::Bound;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use headers::{ETag, HeaderMapExt, IfNoneMatch, IfRange, Range};
+
+use super::error::CDNError;
+
+pub enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// The conditional and range headers of a CDN request. Malformed headers are
+/// ignored, as if they had not been sent.
+pub struct Conditions {
+    pub range: Option<Range>,
+    pub if_range: Option<IfRange>,
+    pub if_none_match: Option<IfNoneMatch>,
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Conditions
+where
+    B: Send,
+{
+    type Rejection = CDNError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().ok_or(CDNError::Internal)?;
+        Ok(Self {
+            range: headers.typed_get(),
+            if_range: headers.typed_get(),
+            if_none_match: headers.typed_get(),
+        })
+    }
+}
+
+impl Conditions {
+    /// Returns whether the client's cached copy with the given ETag is still
+    /// valid, so that the content doesn't need to be sent again.
+    pub fn not_modified(&self, etag: &ETag) -> bool {
+        self.if_none_match
+            .as_ref()
+            .map(|if_none_match| !if_none_match.precondition_passes(etag))
+            .unwrap_or(false)
+    }
+
+    /// Returns the requested range, unless the client's copy differs from the
+    /// content with the given ETag.
+    pub fn range(&self, etag: &ETag) -> Option<&Range> {
+        match &self.if_range {
+            Some(if_range) if if_range.is_modified(Some(etag), None) => None,
+            _ => self.range.as_ref(),
+        }
+    }
+}
+
+impl ByteRange {
+    /// Resolves the `Range` header of a request against a file of `size`
+    /// bytes. Only single ranges are served partially, requests for multiple
+    /// ranges and invalid ranges get the whole file.
+    pub fn resolve(range: &Range, size: u64) -> Self {
+        let mut ranges = range.iter();
+        let bounds = match (ranges.next(), ranges.next()) {
+            (Some(bounds), None) => bounds,
+            _ => return Self::Full,
+        };
+        if size == 0 {
+            return Self::Unsatisfiable;
+        }
+        let (start, end) = match bounds {
+            (Bound::Included(start), Bound::Included(end)) if start <= end => {
+                (start, end.min(size - 1))
+            },
+            (Bound::Included(start), Bound::Unbounded) => (start, size - 1),
+            (Bound::Unbounded, Bound::Included(0)) => return Self::Unsatisfiable,
+            (Bound::Unbounded, Bound::Included(suffix)) => (size.saturating_sub(suffix), size - 1),
+            _ => return Self::Full,
+        };
+        if start >= size {
+            Self::Unsatisfiable
+        } else {
+            Self::Partial(start, end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::*;
+
+    fn range(value: &'static str) -> Range {
+        let mut headers = HeaderMap::new();
+        headers.insert("range", HeaderValue::from_static(value));
+        headers.typed_get().unwrap()
+    }
+
+    fn conditions(values: &[(&'static str, &'static str)]) -> Conditions {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        Conditions {
+            range: headers.typed_get(),
+            if_range: headers.typed_get(),
+            if_none_match: headers.typed_get(),
+        }
+    }
+
+    fn etag(cid: &str) -> ETag {
+        format!("\"{}\"", cid).parse().unwrap()
+    }
+
+    #[test]
+    fn not_modified_by_etag() {
+        let cached = conditions(&[("if-none-match", "\"abc\"")]);
+        assert!(cached.not_modified(&etag("abc")));
+        assert!(!cached.not_modified(&etag("def")));
+        assert!(conditions(&[("if-none-match", "*")]).not_modified(&etag("abc")));
+        assert!(!conditions(&[]).not_modified(&etag("abc")));
+    }
+
+    #[test]
+    fn range_only_if_unchanged() {
+        let matching = conditions(&[("range", "bytes=0-9"), ("if-range", "\"abc\"")]);
+        assert!(matching.range(&etag("abc")).is_some());
+        assert!(matching.range(&etag("def")).is_none());
+        let unconditional = conditions(&[("range", "bytes=0-9")]);
+        assert!(unconditional.range(&etag("abc")).is_some());
+        assert!(conditions(&[]).range(&etag("abc")).is_none());
+    }
+
+    #[test]
+    fn resolve_single_ranges() {
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=0-99"), 1000),
+            ByteRange::Partial(0, 99)
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=900-2000"), 1000),
+            ByteRange::Partial(900, 999)
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=500-"), 1000),
+            ByteRange::Partial(500, 999)
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=-100"), 1000),
+            ByteRange::Partial(900, 999)
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=-2000"), 1000),
+            ByteRange::Partial(0, 999)
+        ));
+    }
+
+    #[test]
+    fn resolve_unsatisfiable_ranges() {
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=1000-"), 1000),
+            ByteRange::Unsatisfiable
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=-0"), 1000),
+            ByteRange::Unsatisfiable
+        ));
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=0-99"), 0),
+            ByteRange::Unsatisfiable
+        ));
+    }
+
+    #[test]
+    fn resolve_multiple_ranges_to_full() {
+        assert!(matches!(
+            ByteRange::resolve(&range("bytes=0-9,20-29"), 1000),
+            ByteRange::Full
+        ));
+    }
+}
@@ -20,6 +20,8 @@ pub enum CDNError {
     Decode(#[from] FromUtf8Error),
     #[error("Header error: {0}")]
     Header(#[from] InvalidHeaderValue),
+    #[error("Bad request: {0}")]
+    BadRequest(&'static str),
     #[error("Internal server error")]
     Internal,
 }
@@ -32,6 +34,8 @@ impl IntoResponse for CDNError {
     fn into_response(self) -> axum::http::Response<Self::Body> {
         let status = match self {
             CDNError::Sql(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            CDNError::Service(ServiceError::NotFound(_)) => StatusCode::NOT_FOUND,
+            CDNError::BadRequest(_) => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
         status.into_response()
@@ -1,80 +1,261 @@
+use std::time::Duration;
+
 use axum::{
     body::Body,
-    extract::{Extension, Path},
+    extract::{Extension, Path, Query},
     handler::get,
     http::HeaderMap,
     response::IntoResponse,
     routing::BoxRoute,
-    Router,
+    Json, Router,
 };
-use headers::{ContentType, HeaderMapExt, HeaderValue};
-use reqwest::{
-    header::{HeaderName, CONTENT_LENGTH},
-    StatusCode,
+use headers::{
+    AcceptRanges, CacheControl, ContentLength, ContentRange, ContentType, ETag, HeaderMapExt,
+    HeaderValue,
 };
+use reqwest::{header::LOCATION, StatusCode};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
 
-use crate::JMService;
+use crate::{
+    extract::{ExtractFormat, Format},
+    v2::models::{CDNDirQuery, CDNEntry},
+    JMService, JMServiceInner,
+};
 
 use self::{
+    conditions::{ByteRange, Conditions},
     error::CDNError,
-    templates::{DirTemplate, HtmlTemplate},
+    templates::DirTemplate,
 };
 
+mod conditions;
 mod error;
 mod sql;
 mod templates;
 
+pub use self::templates::HtmlTemplate;
+
+const USER_FILE_MAX_AGE: Duration = Duration::from_secs(60);
+const CID_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
 pub fn routes() -> Router<BoxRoute> {
     Router::new()
         .route("/", get(users))
         .route("/:user/", get(memes))
         .route("/:user/:filename", get(image))
+        // Below a prefix no route of user files matches, as user IDs may be
+        // `ipfs` or `thumbnails` as well
+        .route("/_/ipfs/:cid", get(ipfs))
+        .route("/_/ipfs/:cid/:filename", get(ipfs_file))
+        .route("/_/thumbnails/:cid", get(thumbnail))
         .boxed()
 }
 
 async fn image(
     Path((user, filename)): Path<(String, String)>,
     Extension(service): Extension<JMService>,
+    conditions: Conditions,
 ) -> Result<impl IntoResponse, CDNError> {
     let filename = urlencoding::decode(&filename)?.into_owned();
     let cid = sql::get_cid(user, filename.clone(), &service.db_pool).await?;
+    // The same filename may be uploaded again with new content, so clients
+    // have to revalidate soon.
+    let cache = CacheControl::new()
+        .with_public()
+        .with_max_age(USER_FILE_MAX_AGE);
+    serve(&service, cid, &filename, cache, conditions).await
+}
+
+async fn ipfs(
+    Path(cid): Path<String>,
+    Extension(service): Extension<JMService>,
+    conditions: Conditions,
+) -> Result<impl IntoResponse, CDNError> {
+    // Only CIDs of memes are served, so the CDN can't be used as an open
+    // IPFS gateway.
+    let filename = sql::get_filename(cid.clone(), &service.db_pool).await?;
+    serve(&service, cid, &filename, cid_cache_control(), conditions).await
+}
+
+async fn ipfs_file(
+    Path((cid, filename)): Path<(String, String)>,
+    Extension(service): Extension<JMService>,
+    conditions: Conditions,
+) -> Result<impl IntoResponse, CDNError> {
+    let filename = urlencoding::decode(&filename)?.into_owned();
+    sql::get_filename(cid.clone(), &service.db_pool).await?;
+    serve(&service, cid, &filename, cid_cache_control(), conditions).await
+}
+
+/// Serves a scaled-down version of a meme for the gallery grid.
+async fn thumbnail(
+    Path(cid): Path<String>,
+    Extension(service): Extension<JMService>,
+    conditions: Conditions,
+) -> Result<impl IntoResponse, CDNError> {
+    let filename = sql::get_filename(cid.clone(), &service.db_pool).await?;
+    let (thumbnail, filename) = service.thumbnail(&cid, &filename).await?;
+    serve(
+        &service,
+        thumbnail,
+        &filename,
+        cid_cache_control(),
+        conditions,
+    )
+    .await
+}
+
+fn cid_cache_control() -> CacheControl {
+    CacheControl::new()
+        .with_public()
+        .with_max_age(CID_MAX_AGE)
+        .with_immutable()
+}
+
+async fn serve(
+    service: &JMServiceInner,
+    cid: String,
+    filename: &str,
+    cache: CacheControl,
+    conditions: Conditions,
+) -> Result<(StatusCode, HeaderMap, Body), CDNError> {
+    let etag = cid_etag(&cid)?;
     let ipfs_path = format!("/ipfs/{}", cid);
-    let res = service.ipfs_cat(cid).await?;
-    let clength = res
-        .headers()
-        .get(HeaderName::from_static("x-content-length"))
-        .ok_or(CDNError::Internal)?;
 
     let mut headers = HeaderMap::new();
+    headers.typed_insert(etag.clone());
+    headers.typed_insert(cache);
+    headers.insert("X-Ipfs-Path", HeaderValue::from_str(ipfs_path.as_str())?);
+
+    if conditions.not_modified(&etag) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Body::empty()));
+    }
+
+    if let Some(url) = service.storage.redirect_url(&cid, filename)? {
+        // The content is immutable, but the URL it is redirected to expires
+        let max_age = service.storage.redirect_max_age().unwrap_or_default();
+        headers.typed_insert(CacheControl::new().with_public().with_max_age(max_age));
+        headers.insert(LOCATION, HeaderValue::from_str(url.as_str())?);
+        return Ok((StatusCode::TEMPORARY_REDIRECT, headers, Body::empty()));
+    }
+
     let ctype = ContentType::from(new_mime_guess::from_path(filename).first_or_octet_stream());
     headers.typed_insert(ctype);
-    headers.insert(CONTENT_LENGTH, clength.clone());
-    headers.insert("X-Ipfs-Path", HeaderValue::from_str(ipfs_path.as_str())?);
+    headers.typed_insert(AcceptRanges::bytes());
+
+    let mut cached = service.cached_cat(&cid).await?;
 
-    Ok((
-        StatusCode::OK,
-        headers,
-        Body::wrap_stream(res.bytes_stream()),
-    ))
+    if let Some(range) = conditions.range(&etag) {
+        let size = match &cached {
+            Some(cached) => cached.size,
+            None => service.storage.size(&cid).await?,
+        };
+        match ByteRange::resolve(range, size) {
+            ByteRange::Partial(start, end) => {
+                let length = end - start + 1;
+                let body = match cached {
+                    Some(mut cached) => {
+                        cached.seek(start).await?;
+                        Body::wrap_stream(ReaderStream::new(cached.file.take(length)))
+                    },
+                    None => {
+                        let content = service.storage.cat_range(&cid, start, length).await?;
+                        Body::wrap_stream(content.stream)
+                    },
+                };
+                headers.typed_insert(
+                    ContentRange::bytes(start..=end, size).map_err(|_| CDNError::Internal)?,
+                );
+                headers.typed_insert(ContentLength(length));
+                return Ok((StatusCode::PARTIAL_CONTENT, headers, body));
+            },
+            ByteRange::Unsatisfiable => {
+                headers.typed_insert(ContentRange::unsatisfied_bytes(size));
+                return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers, Body::empty()));
+            },
+            ByteRange::Full => (),
+        }
+    }
+
+    if let Some(cached) = cached.take() {
+        headers.typed_insert(ContentLength(cached.size));
+        return Ok((
+            StatusCode::OK,
+            headers,
+            Body::wrap_stream(ReaderStream::new(cached.file)),
+        ));
+    }
+
+    let content = service.storage.cat(&cid).await?;
+    if let Some(length) = content.length {
+        headers.typed_insert(ContentLength(length));
+    }
+
+    Ok((StatusCode::OK, headers, Body::wrap_stream(content.stream)))
+}
+
+fn cid_etag(cid: &str) -> Result<ETag, CDNError> {
+    format!("\"{}\"", cid)
+        .parse()
+        .map_err(|_| CDNError::Internal)
 }
 
-async fn users(Extension(service): Extension<JMService>) -> Result<impl IntoResponse, CDNError> {
-    let users = sql::get_users(&service.db_pool).await?;
-    Ok(HtmlTemplate(DirTemplate {
-        entries: users,
-        prefix: service.int_cdn_url(),
-        suffix: "/".to_string(),
-    }))
+/// Postgres rejects a negative `LIMIT` or `OFFSET`, which would otherwise
+/// surface as an internal error.
+fn check_paging(query: &CDNDirQuery) -> Result<(), CDNError> {
+    if query.limit.is_some_and(|limit| limit < 0) {
+        return Err(CDNError::BadRequest("negative limit"));
+    }
+    if query.offset.is_some_and(|offset| offset < 0) {
+        return Err(CDNError::BadRequest("negative offset"));
+    }
+    Ok(())
+}
+
+async fn users(
+    Query(query): Query<CDNDirQuery>,
+    ExtractFormat(format): ExtractFormat,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, CDNError> {
+    check_paging(&query)?;
+    let users = sql::get_users(&query, &service.db_pool).await?;
+    Ok(match format {
+        Format::Json => Json(CDNEntry {
+            directories: users,
+            files: vec![],
+            total: sql::count_users(&service.db_pool).await?,
+        })
+        .into_response(),
+        Format::Html => HtmlTemplate(DirTemplate {
+            entries: users,
+            prefix: service.int_cdn_url(),
+            suffix: "/".to_string(),
+        })
+        .into_response(),
+    })
 }
 
 async fn memes(
     Path(user): Path<String>,
+    Query(query): Query<CDNDirQuery>,
+    ExtractFormat(format): ExtractFormat,
     Extension(service): Extension<JMService>,
 ) -> Result<impl IntoResponse, CDNError> {
-    let memes = sql::get_memes(user, &service.db_pool).await?;
-    Ok(HtmlTemplate(DirTemplate {
-        entries: memes,
-        prefix: ".".to_string(),
-        suffix: "".to_string(),
-    }))
+    check_paging(&query)?;
+    let files = sql::get_files(user.clone(), &query, &service.db_pool).await?;
+    Ok(match format {
+        Format::Json => Json(CDNEntry {
+            directories: vec![],
+            files,
+            total: sql::count_files(user, &service.db_pool).await?,
+        })
+        .into_response(),
+        Format::Html => HtmlTemplate(DirTemplate {
+            entries: files.into_iter().map(|file| file.filename).collect(),
+            prefix: ".".to_string(),
+            suffix: "".to_string(),
+        })
+        .into_response(),
+    })
 }
@@ -1,5 +1,7 @@
 use sqlx::{postgres::PgRow, PgPool, Result, Row};
 
+use crate::v2::models::{CDNDirQuery, CDNFile, CDNSort, SortOrder};
+
 pub async fn get_cid(user: String, filename: String, pool: &PgPool) -> Result<String> {
     let q: String =
         sqlx::query("SELECT cid FROM memes WHERE userid = $1 AND filename = $2 ORDER BY id DESC")
@@ -11,20 +13,73 @@ pub async fn get_cid(user: String, filename: String, pool: &PgPool) -> Result<St
     Ok(q)
 }
 
-pub async fn get_memes(user: String, pool: &PgPool) -> Result<Vec<String>> {
-    let q: Vec<String> =
-        sqlx::query("SELECT filename FROM memes WHERE userid = $1 ORDER BY filename")
+pub async fn get_filename(cid: String, pool: &PgPool) -> Result<String> {
+    let q: String = sqlx::query("SELECT filename FROM memes WHERE cid = $1 ORDER BY id")
+        .bind(cid)
+        .map(|row: PgRow| row.get("filename"))
+        .fetch_one(pool)
+        .await?;
+    Ok(q)
+}
+
+/// Lists the latest version of every file of a user.
+pub async fn get_files(user: String, query: &CDNDirQuery, pool: &PgPool) -> Result<Vec<CDNFile>> {
+    let column = match query.sort.unwrap_or(CDNSort::Name) {
+        CDNSort::Name => "filename",
+        CDNSort::Time => "timestamp",
+        CDNSort::Size => "size",
+    };
+    let sql = format!("SELECT cid, filename, size, UNIX_TIMESTAMP(timestamp) AS ts FROM (SELECT DISTINCT ON (filename) cid, filename, size, timestamp FROM memes WHERE userid = $1 ORDER BY filename, id DESC) AS files ORDER BY {} {}, filename LIMIT $2 OFFSET $3", column, order_sql(query.order));
+    let q: Vec<CDNFile> = sqlx::query(&sql)
+        .bind(user)
+        .bind(query.limit)
+        .bind(query.offset.unwrap_or(0))
+        .map(|row: PgRow| CDNFile {
+            cid: row.get("cid"),
+            filename: row.get("filename"),
+            size: row.get("size"),
+            timestamp: row.get("ts"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn count_files(user: String, pool: &PgPool) -> Result<i64> {
+    let q: i64 =
+        sqlx::query("SELECT COUNT(DISTINCT filename) AS count FROM memes WHERE userid = $1")
             .bind(user)
-            .map(|row: PgRow| row.get("filename"))
-            .fetch_all(pool)
+            .map(|row: PgRow| row.get("count"))
+            .fetch_one(pool)
             .await?;
     Ok(q)
 }
 
-pub async fn get_users(pool: &PgPool) -> Result<Vec<String>> {
-    let q: Vec<String> = sqlx::query("SELECT id FROM users ORDER BY id")
+pub async fn get_users(query: &CDNDirQuery, pool: &PgPool) -> Result<Vec<String>> {
+    let sql = format!(
+        "SELECT id FROM users ORDER BY id {} LIMIT $1 OFFSET $2",
+        order_sql(query.order)
+    );
+    let q: Vec<String> = sqlx::query(&sql)
+        .bind(query.limit)
+        .bind(query.offset.unwrap_or(0))
         .map(|row: PgRow| row.get("id"))
         .fetch_all(pool)
         .await?;
     Ok(q)
 }
+
+pub async fn count_users(pool: &PgPool) -> Result<i64> {
+    let q: i64 = sqlx::query("SELECT COUNT(id) AS count FROM users")
+        .map(|row: PgRow| row.get("count"))
+        .fetch_one(pool)
+        .await?;
+    Ok(q)
+}
+
+fn order_sql(order: Option<SortOrder>) -> &'static str {
+    match order.unwrap_or(SortOrder::Asc) {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    }
+}
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::{sync::OwnedMutexGuard, time::timeout};
+
+use crate::{
+    error::ServiceError, matrix::MATRIX_IP, models::ConsistencyCheck, outbox, JMServiceInner,
+};
+
+use self::sql::CheckMeme;
+
+pub mod sql;
+
+pub const ISSUE_MISSING_PIN: &str = "missing_pin";
+pub const ISSUE_UNFETCHABLE: &str = "unfetchable";
+pub const ISSUE_MISSING_MATRIX: &str = "missing_matrix_event";
+
+const BATCH_SIZE: i64 = 50;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl JMServiceInner {
+    /// Makes sure only one check runs at a time. The guard is passed to
+    /// `run_check`, so it can be taken before the check is spawned.
+    pub fn lock_check(&self) -> Result<OwnedMutexGuard<()>, ServiceError> {
+        self.check_lock
+            .clone()
+            .try_lock_owned()
+            .map_err(|_| ServiceError::CheckRunning)
+    }
+
+    /// Compares up to `limit` memes of the database with the storage and the
+    /// Matrix index if enabled, continuing the last check if it didn't finish.
+    /// Repairs are queued in the outbox, which re-pins or re-sends the meme.
+    pub async fn run_check(
+        &self,
+        _running: OwnedMutexGuard<()>,
+        repair: bool,
+        limit: Option<i64>,
+    ) -> Result<ConsistencyCheck, ServiceError> {
+        let mut check = match sql::get_latest(true, &self.db_pool).await? {
+            Some(check) => check,
+            None => sql::start(&self.db_pool).await?,
+        };
+        let room_id = match self.matrix_enabled() {
+            true => Some(self.room_id().await?),
+            false => None,
+        };
+        let mut remaining = limit.unwrap_or(i64::MAX);
+        let mut repaired = false;
+        let mut checked_cids = HashMap::new();
+
+        while remaining > 0 {
+            let memes =
+                sql::get_memes(check.last_meme, BATCH_SIZE.min(remaining), &self.db_pool).await?;
+            if memes.is_empty() {
+                sql::finish(check.id, &self.db_pool).await?;
+                break;
+            }
+            for meme in &memes {
+                let issues = self
+                    .check_meme(meme, room_id.as_deref(), &mut checked_cids)
+                    .await?;
+                for (issue, action) in issues {
+                    if repair {
+                        sql::queue_repair(meme.id, action, &self.db_pool).await?;
+                        repaired = true;
+                    }
+                    sql::add_issue(check.id, meme.id, issue, repair, &self.db_pool).await?;
+                }
+            }
+            check.last_meme = memes[memes.len() - 1].id;
+            sql::advance(check.id, check.last_meme, memes.len() as i32, &self.db_pool).await?;
+            remaining -= memes.len() as i64;
+        }
+
+        if repaired {
+            self.outbox_notify.notify_one();
+        }
+        self.get_check(check.id).await
+    }
+
+    /// Returns the most recent check with its issues.
+    pub async fn get_latest_check(&self) -> Result<Option<ConsistencyCheck>, ServiceError> {
+        match sql::get_latest(false, &self.db_pool).await? {
+            Some(check) => Ok(Some(self.get_check(check.id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_check(&self, id: i32) -> Result<ConsistencyCheck, ServiceError> {
+        let mut check = sql::get(id, &self.db_pool).await?;
+        check.issues = sql::get_issues(id, &self.db_pool).await?;
+        Ok(check)
+    }
+
+    /// Returns the issues of a meme with the outbox action repairing them.
+    /// The storage issues of every CID are only checked once per run, as
+    /// reuploaded files share it. Errors of the storage or Matrix abort the
+    /// check, so that it doesn't report every meme while a service is down.
+    async fn check_meme(
+        &self,
+        meme: &CheckMeme,
+        room_id: Option<&str>,
+        checked_cids: &mut HashMap<String, Vec<(&'static str, &'static str)>>,
+    ) -> Result<Vec<(&'static str, &'static str)>, ServiceError> {
+        let mut issues = match checked_cids.get(&meme.cid) {
+            Some(issues) => issues.clone(),
+            None => {
+                let issues = self.check_cid(&meme.cid).await?;
+                checked_cids.insert(meme.cid.clone(), issues.clone());
+                issues
+            },
+        };
+        if let Some(room_id) = room_id {
+            let event = match self.get_index(room_id, meme.id).await? {
+                Some(index) => self.get_event(room_id, &index.event_id).await?,
+                None => None,
+            };
+            if event.is_none() {
+                // Memes posted in the room are already there as the message
+                // of the user and only need their meme event
+                let action = match meme.ip.as_deref() {
+                    Some(MATRIX_IP) => outbox::ACTION_MATRIX_EVENT,
+                    _ => outbox::ACTION_MATRIX,
+                };
+                issues.push((ISSUE_MISSING_MATRIX, action));
+            }
+        }
+        Ok(issues)
+    }
+
+    async fn check_cid(
+        &self,
+        cid: &str,
+    ) -> Result<Vec<(&'static str, &'static str)>, ServiceError> {
+        let mut issues = vec![];
+        if !self.storage.is_pinned(cid).await? {
+            issues.push((ISSUE_MISSING_PIN, outbox::ACTION_PIN));
+        }
+        let fetch = timeout(FETCH_TIMEOUT, self.storage.cat_range(cid, 0, 1)).await;
+        if !matches!(fetch, Ok(Ok(_))) {
+            issues.push((ISSUE_UNFETCHABLE, outbox::ACTION_PIN));
+        }
+        Ok(issues)
+    }
+}
@@ -0,0 +1,147 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+use crate::models::{ConsistencyCheck, ConsistencyIssue};
+
+pub struct CheckMeme {
+    pub id: i32,
+    pub cid: String,
+    pub ip: Option<String>,
+}
+
+const CHECK_COLUMNS: &str = "id, UNIX_TIMESTAMP(started) AS started, UNIX_TIMESTAMP(finished) AS finished, last_meme, checked";
+
+fn map_check(row: PgRow) -> ConsistencyCheck {
+    ConsistencyCheck {
+        id: row.get("id"),
+        started: row.get("started"),
+        finished: row.get("finished"),
+        last_meme: row.get("last_meme"),
+        checked: row.get("checked"),
+        issues: vec![],
+    }
+}
+
+/// Returns the most recent check, if `unfinished` is set only if it hasn't
+/// finished yet.
+pub async fn get_latest(unfinished: bool, pool: &PgPool) -> Result<Option<ConsistencyCheck>> {
+    let q: Option<ConsistencyCheck> = sqlx::query(&format!(
+        "SELECT {} FROM consistency_check WHERE finished IS NULL OR NOT $1 ORDER BY id DESC LIMIT 1",
+        CHECK_COLUMNS
+    ))
+    .bind(unfinished)
+    .map(map_check)
+    .fetch_optional(pool)
+    .await?;
+    Ok(q)
+}
+
+pub async fn get(id: i32, pool: &PgPool) -> Result<ConsistencyCheck> {
+    let q: ConsistencyCheck = sqlx::query(&format!(
+        "SELECT {} FROM consistency_check WHERE id = $1",
+        CHECK_COLUMNS
+    ))
+    .bind(id)
+    .map(map_check)
+    .fetch_one(pool)
+    .await?;
+    Ok(q)
+}
+
+pub async fn start(pool: &PgPool) -> Result<ConsistencyCheck> {
+    let q: ConsistencyCheck = sqlx::query(&format!(
+        "INSERT INTO consistency_check DEFAULT VALUES RETURNING {}",
+        CHECK_COLUMNS
+    ))
+    .map(map_check)
+    .fetch_one(pool)
+    .await?;
+    Ok(q)
+}
+
+pub async fn get_memes(after: i32, limit: i64, pool: &PgPool) -> Result<Vec<CheckMeme>> {
+    let q: Vec<CheckMeme> =
+        sqlx::query("SELECT id, cid, ip FROM memes WHERE id > $1 ORDER BY id LIMIT $2")
+            .bind(after)
+            .bind(limit)
+            .map(|row: PgRow| CheckMeme {
+                id: row.get("id"),
+                cid: row.get("cid"),
+                ip: row.get("ip"),
+            })
+            .fetch_all(pool)
+            .await?;
+    Ok(q)
+}
+
+pub async fn add_issue(
+    check: i32,
+    meme: i32,
+    issue: &str,
+    repaired: bool,
+    pool: &PgPool,
+) -> Result<()> {
+    sqlx::query("INSERT INTO consistency_issues (checkid, memeid, issue, repaired) VALUES ($1, $2, $3, $4) ON CONFLICT (checkid, memeid, issue) DO UPDATE SET repaired = consistency_issues.repaired OR EXCLUDED.repaired")
+        .bind(check)
+        .bind(meme)
+        .bind(issue)
+        .bind(repaired)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Saves the progress of a check after a batch, so an interrupted check
+/// resumes after the last meme.
+pub async fn advance(check: i32, last_meme: i32, checked: i32, pool: &PgPool) -> Result<()> {
+    sqlx::query(
+        "UPDATE consistency_check SET last_meme = $2, checked = checked + $3 WHERE id = $1",
+    )
+    .bind(check)
+    .bind(last_meme)
+    .bind(checked)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn finish(check: i32, pool: &PgPool) -> Result<()> {
+    sqlx::query("UPDATE consistency_check SET finished = NOW() WHERE id = $1")
+        .bind(check)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_issues(check: i32, pool: &PgPool) -> Result<Vec<ConsistencyIssue>> {
+    let q: Vec<ConsistencyIssue> = sqlx::query("SELECT memeid, cid, issue, repaired FROM consistency_issues, memes WHERE consistency_issues.memeid = memes.id AND checkid = $1 ORDER BY memeid, issue")
+        .bind(check)
+        .map(|row: PgRow| ConsistencyIssue {
+            meme: row.get("memeid"),
+            cid: row.get("cid"),
+            issue: row.get("issue"),
+            repaired: row.get("repaired"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+/// Queues an outbox action for the meme, unless one is already pending, in
+/// which case it is retried immediately.
+pub async fn queue_repair(meme: i32, action: &str, pool: &PgPool) -> Result<()> {
+    let pending = sqlx::query(
+        "UPDATE outbox SET next_attempt = NOW(), failed = NULL WHERE memeid = $1 AND action = $2 AND done IS NULL",
+    )
+    .bind(meme)
+    .bind(action)
+    .execute(pool)
+    .await?;
+    if pending == 0 {
+        sqlx::query("INSERT INTO outbox (memeid, action) VALUES ($1, $2)")
+            .bind(meme)
+            .bind(action)
+            .execute(pool)
+            .await?;
+    }
+    Ok(())
+}
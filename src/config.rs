@@ -1,9 +1,18 @@
 use reqwest::Url;
 use serde::Deserialize;
 use sqlx::PgPool;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, Notify, OnceCell};
 
-use crate::{error::JMError, JMService, JMServiceInner};
+use crate::{
+    cache::DiskCache,
+    error::JMError,
+    ipfs::{nodes::IpfsNodes, IpfsStorage},
+    matrix::Matrix,
+    models::User,
+    storage::{LocalStorage, S3Storage, Storage},
+    JMService, JMServiceInner,
+};
 
 #[derive(Deserialize)]
 pub struct Config {
@@ -11,28 +20,243 @@ pub struct Config {
     pub database: String,
     pub int_cdn: String,
     pub ext_cdn: String,
-    pub ipfs_api: Url,
-    pub matrix_url: Url,
-    pub matrix_token: String,
-    pub matrix_domain: String,
+    pub public_url: Option<String>,
+    pub storage: Option<StorageConfig>,
+    pub ipfs_api: Option<OneOrMany<Url>>,
+    #[serde(default)]
+    pub ipfs_gateways: Vec<Url>,
+    #[serde(flatten)]
+    pub matrix: MatrixConfig,
+    #[serde(default)]
+    pub admins: Vec<String>,
+    pub cache: Option<CacheConfig>,
+    pub mfs: Option<MfsConfig>,
+    pub gc: Option<GcConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    Ipfs,
+    Local { dir: PathBuf },
+    S3(S3Config),
+}
+
+#[derive(Deserialize)]
+pub struct S3Config {
+    pub endpoint: Url,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Redirect CDN reads to presigned URLs valid for this many seconds
+    /// instead of streaming them through the server.
+    pub presign_expiry: Option<u64>,
+}
+
+/// Mirrors new memes into a Matrix room through an application service.
+/// The keys keep their `matrix_` prefix at the top level of the config.
+#[derive(Deserialize)]
+pub struct MatrixConfig {
+    #[serde(rename = "matrix_enabled", default = "default_matrix_enabled")]
+    pub enabled: bool,
+    #[serde(rename = "matrix_url")]
+    pub url: Option<Url>,
+    #[serde(rename = "matrix_token")]
+    pub token: Option<String>,
+    /// Token the homeserver authenticates with when pushing transactions
+    #[serde(rename = "matrix_hs_token")]
+    pub hs_token: Option<String>,
+    #[serde(rename = "matrix_domain")]
+    pub domain: Option<String>,
+    #[serde(rename = "matrix_appservice_id", default = "default_appservice_id")]
+    pub as_id: String,
+    /// Localpart of the user the `matrix_token` belongs to
+    #[serde(rename = "matrix_sender", default = "default_appservice_id")]
+    pub sender: String,
+    #[serde(rename = "matrix_room", default = "default_matrix_room")]
+    pub room: String,
+    #[serde(rename = "matrix_meme_event", default = "default_meme_event")]
+    pub meme_event: String,
+    #[serde(rename = "matrix_index_event", default = "default_index_event")]
+    pub index_event: String,
+    #[serde(rename = "matrix_user_prefix", default = "default_user_prefix")]
+    pub user_prefix: String,
+    /// Category of media posted in the room without a `!meme category`
+    /// caption, media without either is not ingested
+    #[serde(rename = "matrix_category")]
+    pub category: Option<String>,
+}
+
+/// Keeps all memes in the MFS directory `root` of the first IPFS API node,
+/// optionally publishing it under an IPNS key.
+#[derive(Deserialize, Clone)]
+pub struct MfsConfig {
+    #[serde(default = "default_mfs_root")]
+    pub root: String,
+    pub ipns_key: Option<String>,
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval: u64,
+    #[serde(default = "default_publish_interval")]
+    pub publish_interval: u64,
+}
+
+/// Unpins CIDs that are not referenced by the database anymore.
+#[derive(Deserialize, Clone)]
+pub struct GcConfig {
+    #[serde(default = "default_gc_interval")]
+    pub interval: u64,
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+}
+
+#[derive(Deserialize)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    pub max_size: u64,
 }
 
 impl Config {
     pub fn service(&self, db_pool: PgPool) -> Result<JMService, JMError> {
-        let client = reqwest::ClientBuilder::new().user_agent("curl").build()?;
+        let client = reqwest::ClientBuilder::new()
+            .user_agent("curl")
+            .connect_timeout(Duration::from_secs(10))
+            .build()?;
+        let storage: Box<dyn Storage> = match &self.storage {
+            None | Some(StorageConfig::Ipfs) => {
+                let apis = self.ipfs_api.as_ref().ok_or_else(|| {
+                    JMError::Config("ipfs_api is required for the IPFS storage".to_string())
+                })?;
+                Box::new(IpfsStorage::new(
+                    client.clone(),
+                    IpfsNodes::new(apis.to_vec(), self.ipfs_gateways.clone()),
+                ))
+            },
+            Some(StorageConfig::Local { dir }) => Box::new(LocalStorage::new(dir.clone())?),
+            Some(StorageConfig::S3(s3)) => Box::new(S3Storage::new(client.clone(), s3)),
+        };
+        if self.mfs.is_some() && storage.ipfs().is_none() {
+            return Err(JMError::Config("mfs requires the IPFS storage".to_string()));
+        }
+        if self.gc.is_some() && storage.ipfs().is_none() {
+            return Err(JMError::Config("gc requires the IPFS storage".to_string()));
+        }
         Ok(Arc::new(JMServiceInner {
             client,
             db_pool,
-            ipfs_url: self.ipfs_api.clone(),
+            storage,
             int_cdn: self.int_cdn.clone(),
             ext_cdn: self.ext_cdn.clone(),
-            matrix_url: self.matrix_url.clone(),
-            matrix_token: self.matrix_token.clone(),
-            matrix_domain: self.matrix_domain.clone(),
+            public_url: self
+                .public_url
+                .as_deref()
+                .unwrap_or_default()
+                .trim_end_matches('/')
+                .to_string(),
+            matrix: self.matrix.build()?,
+            admins: self.admins.clone(),
+            cache: self.cache.as_ref().map(DiskCache::new).transpose()?,
+            outbox_notify: Notify::new(),
+            check_lock: Arc::new(Mutex::new(())),
+            mfs: self.mfs.clone(),
+            mfs_notify: Notify::new(),
+            profile_notify: Notify::new(),
+            gc: self.gc.clone(),
         }))
     }
 }
 
+impl MatrixConfig {
+    fn build(&self) -> Result<Option<Matrix>, JMError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let missing = |key: &str| JMError::Config(format!("{} is required for Matrix", key));
+        Ok(Some(Matrix {
+            url: self.url.clone().ok_or_else(|| missing("matrix_url"))?,
+            token: self.token.clone().ok_or_else(|| missing("matrix_token"))?,
+            hs_token: self.hs_token.clone(),
+            domain: self
+                .domain
+                .clone()
+                .ok_or_else(|| missing("matrix_domain"))?,
+            as_id: self.as_id.clone(),
+            sender: self.sender.clone(),
+            room: self.room.clone(),
+            meme_event: self.meme_event.clone(),
+            index_event: self.index_event.clone(),
+            user_prefix: self.user_prefix.clone(),
+            category: self.category.clone(),
+            room_id: OnceCell::new(),
+        }))
+    }
+}
+
+fn default_matrix_enabled() -> bool {
+    true
+}
+
+fn default_appservice_id() -> String {
+    "jensmemes".to_string()
+}
+
+fn default_matrix_room() -> String {
+    "#memes:tilera.org".to_string()
+}
+
+fn default_meme_event() -> String {
+    "es.jensmem.meme".to_string()
+}
+
+fn default_index_event() -> String {
+    "es.jensmem.index".to_string()
+}
+
+fn default_user_prefix() -> String {
+    "jm_".to_string()
+}
+
+fn default_mfs_root() -> String {
+    "/jensmemes".to_string()
+}
+
+fn default_sync_interval() -> u64 {
+    10 * 60
+}
+
+fn default_publish_interval() -> u64 {
+    60 * 60
+}
+
+fn default_gc_interval() -> u64 {
+    24 * 60 * 60
+}
+
+pub fn default_grace_period() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+impl<T: Clone> OneOrMany<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        match self {
+            OneOrMany::One(value) => vec![value.clone()],
+            OneOrMany::Many(values) => values.clone(),
+        }
+    }
+}
+
 impl JMServiceInner {
     pub fn int_cdn_url(&self) -> String {
         self.int_cdn.clone()
@@ -41,4 +265,12 @@ impl JMServiceInner {
     pub fn ext_cdn_url(&self) -> String {
         self.ext_cdn.clone()
     }
+
+    pub fn public_url(&self) -> String {
+        self.public_url.clone()
+    }
+
+    pub fn is_admin(&self, user: &User) -> bool {
+        self.admins.contains(&user.id)
+    }
 }
@@ -1,7 +1,7 @@
 use std::string::FromUtf8Error;
 
 use axum::extract::{multipart::MultipartError, rejection::QueryRejection};
-use hyper::StatusCode;
+use hyper::{header::InvalidHeaderValue, StatusCode};
 use thiserror::Error;
 use url::ParseError;
 
@@ -11,12 +11,20 @@ pub enum JMError {
     Read(#[from] std::io::Error),
     #[error("Deserialize error: {0}")]
     Deserialize(#[from] toml::de::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("YAML error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
     #[error("Database connection error: {0}")]
     Database(#[from] sqlx::Error),
     #[error("Axum error: {0}")]
     Axum(#[from] hyper::Error),
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
+    #[error("JMService error: {0}")]
+    Service(#[from] ServiceError),
+    #[error("Config error: {0}")]
+    Config(String),
 }
 
 #[derive(Error, Debug)]
@@ -27,6 +35,30 @@ pub enum ServiceError {
     Url(#[from] ParseError),
     #[error("Invalid response code: {0}")]
     InvalidResponse(StatusCode),
+    #[error("Unknown outbox action: {0}")]
+    UnknownAction(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("No IPFS node available")]
+    NoIpfsNode,
+    #[error("IPFS nodes failed: {0}")]
+    IpfsNodes(String),
+    #[error("Content not found: {0}")]
+    NotFound(String),
+    #[error("Invalid header value: {0}")]
+    Header(#[from] InvalidHeaderValue),
+    #[error("SQL error: {0}")]
+    Sql(#[from] sqlx::Error),
+    #[error("A consistency check is already running")]
+    CheckRunning,
+    #[error("Invalid CID: {0}")]
+    InvalidCid(String),
+    #[error("Matrix is disabled")]
+    MatrixDisabled,
+    #[error("The {0} table is not empty")]
+    NotEmpty(String),
+    #[error("File larger than {0} bytes")]
+    TooLarge(u64),
 }
 
 #[derive(Error, Debug)]
@@ -44,6 +76,8 @@ pub enum APIError {
     #[error("{0}")]
     NotFound(String),
     #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
     Internal(String),
     #[error("JMService error: {0}")]
     Service(#[from] ServiceError),
@@ -0,0 +1,133 @@
+//! Encoding of CARv1 archives, see <https://ipld.io/specs/transport/car/carv1/>.
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Returns the binary form of a CIDv0 (`Qm...`) or a base32 CIDv1 (`b...`).
+pub fn parse_cid(cid: &str) -> Option<Vec<u8>> {
+    if cid.len() == 46 && cid.starts_with("Qm") {
+        base58_decode(cid)
+    } else if let Some(data) = cid.strip_prefix('b') {
+        base32_decode(data)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the binary CID is a CIDv1 with the raw codec, so its
+/// block is the content itself.
+pub fn is_raw(cid: &[u8]) -> bool {
+    cid.starts_with(&[0x01, 0x55])
+}
+
+/// Encodes the header with the given roots, prefixed with its length.
+pub fn header(roots: &[Vec<u8>]) -> Vec<u8> {
+    let mut cbor = vec![0xa2];
+    cbor_string("roots", &mut cbor);
+    cbor_head(4, roots.len() as u64, &mut cbor);
+    for root in roots {
+        // CIDs are tagged byte strings with a leading zero byte in DAG-CBOR
+        cbor.extend_from_slice(&[0xd8, 42]);
+        cbor_head(2, root.len() as u64 + 1, &mut cbor);
+        cbor.push(0);
+        cbor.extend_from_slice(root);
+    }
+    cbor_string("version", &mut cbor);
+    cbor_head(0, 1, &mut cbor);
+
+    let mut out = vec![];
+    varint(cbor.len() as u64, &mut out);
+    out.extend_from_slice(&cbor);
+    out
+}
+
+/// Encodes the part of a block preceding its data.
+pub fn block_prefix(cid: &[u8], length: u64) -> Vec<u8> {
+    let mut out = vec![];
+    varint(cid.len() as u64 + length, &mut out);
+    out.extend_from_slice(cid);
+    out
+}
+
+pub async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = reader.read_u8().await?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok(value)
+}
+
+fn varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn cbor_head(major: u8, value: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.extend_from_slice(&[major | 24, value as u8]);
+    } else if value <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn cbor_string(value: &str, out: &mut Vec<u8>) {
+    cbor_head(3, value.len() as u64, out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for c in data.bytes() {
+        let value = match c {
+            b'a'..=b'z' => c - b'a',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn base58_decode(data: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut out: Vec<u8> = vec![];
+    for c in data.bytes() {
+        let mut carry = ALPHABET.iter().position(|a| *a == c)? as u32;
+        for byte in out.iter_mut().rev() {
+            carry += (*byte as u32) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            out.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = data.bytes().take_while(|c| *c == b'1').count();
+    let mut result = vec![0; zeros];
+    result.extend(out);
+    Some(result)
+}
@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+
+use axum::body::Bytes;
+use chrono::Utc;
+use futures_util::{stream, StreamExt, TryStreamExt};
+use serde::Serialize;
+
+use crate::{
+    error::ServiceError, models::Meme, storage::ByteStream, v2::models::V2Meme, JMService,
+};
+
+pub mod car;
+
+pub const CAR_CONTENT_TYPE: &str = "application/vnd.ipld.car";
+
+/// Metadata of the memes in an exported CAR archive.
+#[derive(Serialize)]
+pub struct ExportManifest {
+    pub created: i64,
+    pub roots: Vec<String>,
+    pub memes: Vec<V2Meme>,
+}
+
+impl ExportManifest {
+    pub fn new(memes: Vec<Meme>) -> Self {
+        Self {
+            created: Utc::now().timestamp(),
+            roots: unique_cids(&memes),
+            memes: memes.into_iter().map(V2Meme::from).collect(),
+        }
+    }
+}
+
+/// Streams a CARv1 archive with the files of the memes as its roots. Every
+/// file is only included once, even if several memes share it.
+pub fn car_stream(service: JMService, memes: &[Meme]) -> Result<ByteStream, ServiceError> {
+    let cids = unique_cids(memes);
+    let roots = cids
+        .iter()
+        .map(|cid| car::parse_cid(cid).ok_or_else(|| ServiceError::InvalidCid(cid.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    let header = Bytes::from(car::header(&roots));
+    let blocks = stream::iter(cids)
+        .then(move |cid| {
+            let service = service.clone();
+            async move { service.storage.car_blocks(&cid).await }
+        })
+        .try_flatten();
+    Ok(stream::once(async move { Ok(header) })
+        .chain(blocks)
+        .boxed())
+}
+
+fn unique_cids(memes: &[Meme]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    memes
+        .iter()
+        .filter(|meme| seen.insert(meme.ipfs.as_str()))
+        .map(|meme| meme.ipfs.clone())
+        .collect()
+}
@@ -0,0 +1,51 @@
+use std::convert::Infallible;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+
+pub enum Format {
+    Html,
+    Json,
+}
+
+/// Negotiates between an HTML and a JSON response based on the `Accept`
+/// header. JSON is only chosen if the client explicitly prefers it.
+pub struct ExtractFormat(pub Format);
+
+#[async_trait]
+impl<B> FromRequest<B> for ExtractFormat
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let accept = req
+            .headers()
+            .and_then(|headers| headers.get("accept"))
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let mut json = 0.0;
+        let mut html = 0.0;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let mime = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            match mime {
+                "application/json" => json = quality.max(json),
+                "text/html" | "text/*" | "*/*" => html = quality.max(html),
+                _ => (),
+            }
+        }
+
+        if json > 0.0 && json >= html {
+            Ok(Self(Format::Json))
+        } else {
+            Ok(Self(Format::Html))
+        }
+    }
+}
@@ -15,6 +15,8 @@ pub enum ExtractError {
     ToStr(#[from] ToStrError),
     #[error("Header value is not a valid IP address: {0}")]
     IPParse(#[from] AddrParseError),
+    #[error("Authorization header must use the `{0}` scheme")]
+    InvalidScheme(String),
 }
 
 impl IntoResponse for ExtractError {
@@ -0,0 +1,8 @@
+mod accept;
+mod error;
+mod ipheader;
+mod token;
+
+pub use accept::{ExtractFormat, Format};
+pub use ipheader::ExtractIP;
+pub use token::ExtractToken;
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+
+use super::error::ExtractError;
+
+pub struct ExtractToken(pub String);
+
+#[async_trait]
+impl<B> FromRequest<B> for ExtractToken
+where
+    B: Send,
+{
+    type Rejection = ExtractError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let header = req
+            .headers()
+            .and_then(|headers| headers.get("authorization"));
+        let header =
+            header.ok_or_else(|| ExtractError::HeaderMissing("Authorization".to_string()))?;
+        let value = header.to_str()?;
+        let token = value
+            .strip_prefix("Token ")
+            .ok_or_else(|| ExtractError::InvalidScheme("Token".to_string()))?;
+
+        Ok(Self(token.trim().to_string()))
+    }
+}
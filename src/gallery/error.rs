@@ -0,0 +1,47 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::{Bytes, Full},
+    response::{Html, IntoResponse},
+};
+use reqwest::StatusCode;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GalleryError {
+    #[error("SQL error: {0}")]
+    Sql(#[from] sqlx::Error),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    NotImplemented(String),
+}
+
+impl IntoResponse for GalleryError {
+    type Body = Full<Bytes>;
+
+    type BodyError = Infallible;
+
+    fn into_response(self) -> axum::http::Response<Self::Body> {
+        let (status, message) = match self {
+            GalleryError::Sql(sqlx::Error::RowNotFound) => {
+                (StatusCode::NOT_FOUND, "Not found".to_string())
+            },
+            GalleryError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            GalleryError::NotImplemented(message) => (StatusCode::NOT_IMPLEMENTED, message),
+            GalleryError::Sql(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            ),
+        };
+        (
+            status,
+            Html(format!(
+                "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"UTF-8\"><title>{0}</title></head><body><h1>{0}</h1><p><a href=\"{1}/\">Back to JensMemes</a></p></body></html>",
+                message,
+                super::BASE
+            )),
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,211 @@
+use axum::{
+    extract::{Extension, Path, Query},
+    handler::get,
+    response::IntoResponse,
+    routing::BoxRoute,
+    Router,
+};
+use serde::Deserialize;
+
+use crate::{
+    cdn::HtmlTemplate,
+    models::{Category, MemeOptions, UserIdentifier},
+    JMService,
+};
+
+use self::{
+    error::GalleryError,
+    templates::{GalleryMeme, GridTemplate, MemeTemplate, UsersTemplate},
+};
+
+mod error;
+mod oembed;
+mod templates;
+
+pub use oembed::oembed;
+
+pub const BASE: &str = "/gallery";
+const PAGE_SIZE: i32 = 48;
+
+#[derive(Deserialize)]
+pub struct GalleryQuery {
+    pub q: Option<String>,
+    pub category: Option<String>,
+    pub after: Option<i32>,
+}
+
+pub fn routes() -> Router<BoxRoute> {
+    Router::new()
+        .route("/", get(index))
+        .route("/categories/:category_id", get(category))
+        .route("/users", get(users))
+        .route("/users/:user_id", get(user))
+        .route("/memes/:meme_id", get(meme))
+        .boxed()
+}
+
+async fn index(
+    Query(query): Query<GalleryQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    let title = match &query.q {
+        Some(q) if !q.is_empty() => format!("Search results for \"{}\"", q),
+        _ => "Memes".to_string(),
+    };
+    let options = MemeOptions {
+        category: query.category.clone().filter(|c| !c.is_empty()),
+        search: query.q.clone(),
+        ..MemeOptions::empty()
+    };
+    grid(&service, format!("{}/", BASE), title, options, query).await
+}
+
+async fn category(
+    Path(category_id): Path<String>,
+    Query(query): Query<GalleryQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    let category = service
+        .get_category(&category_id)
+        .await?
+        .ok_or_else(|| GalleryError::NotFound("Category not found".to_string()))?;
+    let options = MemeOptions {
+        category: Some(category.id.clone()),
+        ..MemeOptions::empty()
+    };
+    let path = format!("{}/categories/{}", BASE, urlencoding::encode(&category.id));
+    grid(&service, path, category.name, options, query).await
+}
+
+async fn user(
+    Path(user_id): Path<String>,
+    Query(query): Query<GalleryQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    let user = service
+        .get_user(UserIdentifier::Id(user_id))
+        .await?
+        .ok_or_else(|| GalleryError::NotFound("User not found".to_string()))?;
+    let options = MemeOptions {
+        user_id: Some(user.id.clone()),
+        ..MemeOptions::empty()
+    };
+    let path = format!("{}/users/{}", BASE, urlencoding::encode(&user.id));
+    grid(&service, path, user.name, options, query).await
+}
+
+async fn users(
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    Ok(HtmlTemplate(UsersTemplate {
+        base: BASE.to_string(),
+        categories: service.get_categories().await?,
+        search: String::new(),
+        category: String::new(),
+        users: service.get_users().await?,
+    }))
+}
+
+async fn meme(
+    Path(meme_id): Path<i32>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    let meme = service
+        .get_meme(meme_id)
+        .await?
+        .ok_or_else(|| GalleryError::NotFound("Meme not found".to_string()))?;
+    let categories = service.get_categories().await?;
+    let url = format!("{}{}/memes/{}", service.public_url(), BASE, meme.id);
+    let oembed = format!(
+        "{}/oembed?url={}&format=json",
+        service.public_url(),
+        urlencoding::encode(&url)
+    );
+    let description = format!(
+        "Uploaded by {} in {}",
+        meme.username,
+        category_name(&categories, &meme.category)
+    );
+    Ok(HtmlTemplate(MemeTemplate {
+        base: BASE.to_string(),
+        categories,
+        search: String::new(),
+        category: String::new(),
+        meme: GalleryMeme::new(meme, &service.ext_cdn_url()),
+        url,
+        oembed,
+        description,
+    }))
+}
+
+fn category_name<'a>(categories: &'a [Category], id: &'a str) -> &'a str {
+    categories
+        .iter()
+        .find(|category| category.id == id)
+        .map(|category| category.name.as_str())
+        .unwrap_or(id)
+}
+
+/// Renders one page of memes. Pages are addressed by the ID of the last meme
+/// on the previous page, like the `after` parameter of the v2 API.
+async fn grid(
+    service: &JMService,
+    path: String,
+    title: String,
+    options: MemeOptions,
+    query: GalleryQuery,
+) -> Result<HtmlTemplate<GridTemplate>, GalleryError> {
+    let categories: Vec<Category> = service.get_categories().await?;
+    let cdn = service.ext_cdn_url();
+    let memes: Vec<GalleryMeme> = service
+        .get_memes(MemeOptions {
+            limit: Some(PAGE_SIZE),
+            after: query.after,
+            ..options
+        })
+        .await?
+        .into_iter()
+        .map(|meme| GalleryMeme::new(meme, &cdn))
+        .collect();
+
+    let search = query.q.unwrap_or_default();
+    let category = query.category.unwrap_or_default();
+    let mut params = vec![];
+    if !search.is_empty() {
+        params.push(format!("q={}", urlencoding::encode(&search)));
+    }
+    if !category.is_empty() {
+        params.push(format!("category={}", urlencoding::encode(&category)));
+    }
+    let page = |after: Option<i32>| {
+        let mut params = params.clone();
+        if let Some(after) = after {
+            params.push(format!("after={}", after));
+        }
+        if params.is_empty() {
+            path.clone()
+        } else {
+            format!("{}?{}", path, params.join("&"))
+        }
+    };
+
+    let first = match query.after {
+        Some(_) => page(None),
+        None => String::new(),
+    };
+    let next = match memes.last() {
+        Some(last) if memes.len() == PAGE_SIZE as usize => page(Some(last.id)),
+        _ => String::new(),
+    };
+
+    Ok(HtmlTemplate(GridTemplate {
+        base: BASE.to_string(),
+        categories,
+        search,
+        category,
+        title,
+        memes,
+        first,
+        next,
+    }))
+}
@@ -0,0 +1,125 @@
+use axum::{
+    extract::{Extension, Query},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::JMService;
+
+use super::{error::GalleryError, BASE};
+
+#[derive(Deserialize)]
+pub struct OEmbedQuery {
+    pub url: String,
+    pub format: Option<String>,
+    pub maxwidth: Option<u32>,
+    pub maxheight: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct OEmbed {
+    pub version: &'static str,
+    #[serde(rename = "type")]
+    pub embed_type: &'static str,
+    pub title: String,
+    pub author_name: String,
+    pub author_url: String,
+    pub provider_name: &'static str,
+    pub provider_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// oEmbed endpoint for the share pages of memes. Images are embedded as
+/// photos, everything else only as a link, since no player is available.
+pub async fn oembed(
+    Query(query): Query<OEmbedQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, GalleryError> {
+    if query.format.as_deref().unwrap_or("json") != "json" {
+        return Err(GalleryError::NotImplemented(
+            "Only the json format is supported".to_string(),
+        ));
+    }
+    let public_url = service.public_url();
+    let meme_id = meme_id(&query.url, &public_url)
+        .ok_or_else(|| GalleryError::NotFound("URL is not a meme".to_string()))?;
+    let meme = service
+        .get_meme(meme_id)
+        .await?
+        .ok_or_else(|| GalleryError::NotFound("Meme not found".to_string()))?;
+
+    let mime = new_mime_guess::from_path(&meme.filename).first_or_octet_stream();
+    let dimensions = if mime.type_() == "image" {
+        // Without dimensions the meme can only be embedded as a link
+        service
+            .probe_dimensions(meme.ipfs.clone())
+            .await
+            .ok()
+            .flatten()
+            .map(|(width, height)| fit(width, height, query.maxwidth, query.maxheight))
+    } else {
+        None
+    };
+
+    let link = format!(
+        "{}/{}/{}",
+        service.ext_cdn_url(),
+        meme.userid,
+        urlencoding::encode(&meme.filename)
+    );
+    let category = service
+        .get_category(&meme.category)
+        .await?
+        .map(|category| category.name)
+        .unwrap_or(meme.category);
+
+    Ok(Json(OEmbed {
+        version: "1.0",
+        embed_type: if dimensions.is_some() {
+            "photo"
+        } else {
+            "link"
+        },
+        title: format!("{} ({})", meme.filename, category),
+        author_name: meme.username,
+        author_url: format!("{}{}/users/{}", public_url, BASE, meme.userid),
+        provider_name: "JensMemes",
+        provider_url: format!("{}{}/", public_url, BASE),
+        url: dimensions.map(|_| link),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+    }))
+}
+
+/// Only share pages of this gallery are answered, not memes on other hosts.
+fn meme_id(url: &str, public_url: &str) -> Option<i32> {
+    let url = Url::parse(url).ok()?;
+    let base = Url::parse(&format!("{}{}/memes/", public_url, BASE)).ok()?;
+    if url.origin() != base.origin() {
+        return None;
+    }
+    url.path().strip_prefix(base.path())?.parse().ok()
+}
+
+/// Scales the dimensions down to fit into the maximum size requested by the
+/// consumer, keeping the aspect ratio.
+fn fit(width: u32, height: u32, maxwidth: Option<u32>, maxheight: Option<u32>) -> (u32, u32) {
+    let mut scale: f64 = 1.0;
+    if let Some(maxwidth) = maxwidth {
+        scale = scale.min(maxwidth as f64 / width.max(1) as f64);
+    }
+    if let Some(maxheight) = maxheight {
+        scale = scale.min(maxheight as f64 / height.max(1) as f64);
+    }
+    (
+        ((width as f64) * scale).round() as u32,
+        ((height as f64) * scale).round() as u32,
+    )
+}
@@ -0,0 +1,87 @@
+use askama::Template;
+use chrono::DateTime;
+
+use crate::models::{Category, Meme, User};
+
+pub struct GalleryMeme {
+    pub id: i32,
+    pub filename: String,
+    pub user: String,
+    pub username: String,
+    pub category: String,
+    pub date: String,
+    pub ipfs: String,
+    pub link: String,
+    pub ipfs_link: String,
+    pub thumbnail: String,
+    pub mime: String,
+    pub video: bool,
+}
+
+#[derive(Template)]
+#[template(path = "gallery/grid.html")]
+pub struct GridTemplate {
+    pub base: String,
+    pub categories: Vec<Category>,
+    pub search: String,
+    pub category: String,
+    pub title: String,
+    pub memes: Vec<GalleryMeme>,
+    pub first: String,
+    pub next: String,
+}
+
+#[derive(Template)]
+#[template(path = "gallery/meme.html")]
+pub struct MemeTemplate {
+    pub base: String,
+    pub categories: Vec<Category>,
+    pub search: String,
+    pub category: String,
+    pub meme: GalleryMeme,
+    pub url: String,
+    pub oembed: String,
+    pub description: String,
+}
+
+#[derive(Template)]
+#[template(path = "gallery/users.html")]
+pub struct UsersTemplate {
+    pub base: String,
+    pub categories: Vec<Category>,
+    pub search: String,
+    pub category: String,
+    pub users: Vec<User>,
+}
+
+impl GalleryMeme {
+    pub fn new(meme: Meme, cdn: &str) -> Self {
+        let mime = new_mime_guess::from_path(&meme.filename).first_or_octet_stream();
+        Self {
+            link: format!(
+                "{}/{}/{}",
+                cdn,
+                meme.userid,
+                urlencoding::encode(&meme.filename)
+            ),
+            ipfs_link: format!("{}/_/ipfs/{}", cdn, meme.ipfs),
+            thumbnail: format!("{}/_/thumbnails/{}", cdn, meme.ipfs),
+            video: mime.type_() == "video",
+            mime: mime.to_string(),
+            date: format_date(meme.timestamp),
+            id: meme.id,
+            filename: meme.filename,
+            user: meme.userid,
+            username: meme.username,
+            category: meme.category,
+            ipfs: meme.ipfs,
+        }
+    }
+}
+
+/// Formats a unix timestamp as an ISO 8601 date in UTC.
+pub fn format_date(timestamp: i32) -> String {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
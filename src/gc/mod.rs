@@ -0,0 +1,97 @@
+use std::{collections::HashSet, time::Duration};
+
+use crate::{error::ServiceError, JMService, JMServiceInner};
+
+mod sql;
+
+pub struct OrphanPin {
+    pub cid: String,
+    /// Seconds since the pin was found unreferenced first
+    pub age: i64,
+    pub unpinned: bool,
+}
+
+pub struct GcReport {
+    pub pins: usize,
+    pub orphans: Vec<OrphanPin>,
+}
+
+/// Collects garbage at every interval of the `gc` config option.
+pub async fn run(service: JMService) {
+    let config = match &service.gc {
+        Some(config) => config,
+        None => return,
+    };
+    loop {
+        match service.collect_pins(false, config.grace_period).await {
+            Ok(report) => {
+                let unpinned = report.orphans.iter().filter(|pin| pin.unpinned).count();
+                if unpinned > 0 {
+                    eprintln!("GC unpinned {} of {} pins", unpinned, report.pins);
+                }
+            },
+            Err(err) => eprintln!("GC error: {}", err),
+        }
+        tokio::time::sleep(Duration::from_secs(config.interval)).await;
+    }
+}
+
+impl JMServiceInner {
+    /// Pins the CID in the storage, recording it first so that the garbage
+    /// collector only ever unpins what the server pinned itself.
+    pub async fn pin(&self, cid: &str) -> Result<(), ServiceError> {
+        sql::add_pin(cid, &self.db_pool).await?;
+        self.storage.pin(cid).await
+    }
+
+    /// Unpins every CID the server pinned that is not referenced by the
+    /// database and has been unreferenced for longer than the grace period,
+    /// so files of uploads that are still being inserted are kept. Pins
+    /// created by others on the same node are never touched. In report mode
+    /// nothing is unpinned, but orphans are still tracked for the grace
+    /// period.
+    pub async fn collect_pins(
+        &self,
+        report_only: bool,
+        grace_period: u64,
+    ) -> Result<GcReport, ServiceError> {
+        let ipfs = self.storage.ipfs().ok_or(ServiceError::NoIpfsNode)?;
+        // The pins are listed before the references are read, so CIDs of
+        // memes inserted in between are not taken for orphans.
+        let added = sql::get_added_pins(&self.db_pool).await?;
+        let pins: HashSet<String> = ipfs
+            .ipfs_pins()
+            .await?
+            .into_iter()
+            .filter(|cid| added.contains(cid))
+            .collect();
+        let referenced = sql::get_referenced_cids(&self.db_pool).await?;
+
+        for cid in sql::get_tracked(&self.db_pool).await? {
+            if referenced.contains(&cid) || !pins.contains(&cid) {
+                sql::forget(&cid, &self.db_pool).await?;
+            }
+        }
+
+        let mut orphans = vec![];
+        for cid in pins.iter().filter(|cid| !referenced.contains(*cid)) {
+            let age = sql::track_orphan(cid, &self.db_pool).await?;
+            let unpinned = !report_only && age >= grace_period as i64;
+            if unpinned {
+                ipfs.ipfs_unpin(cid.clone()).await?;
+                sql::forget(cid, &self.db_pool).await?;
+                sql::remove_pin(cid, &self.db_pool).await?;
+            }
+            orphans.push(OrphanPin {
+                cid: cid.clone(),
+                age,
+                unpinned,
+            });
+        }
+        orphans.sort_by_key(|pin| std::cmp::Reverse(pin.age));
+        Ok(GcReport {
+            pins: pins.len(),
+            orphans,
+        })
+    }
+}
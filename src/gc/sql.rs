@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+/// Returns every CID the database references.
+pub async fn get_referenced_cids(pool: &PgPool) -> Result<HashSet<String>> {
+    let q: Vec<String> = sqlx::query(
+        "SELECT cid FROM memes UNION SELECT avatar FROM users WHERE avatar IS NOT NULL UNION SELECT thumbnail FROM thumbnails",
+    )
+    .map(|row: PgRow| row.get("cid"))
+    .fetch_all(pool)
+    .await?;
+    Ok(q.into_iter().collect())
+}
+
+/// Remembers that the CID is pinned by the server, so that the garbage
+/// collector may unpin it.
+pub async fn add_pin(cid: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO added_pins (cid) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(cid)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns every CID the server pinned.
+pub async fn get_added_pins(pool: &PgPool) -> Result<HashSet<String>> {
+    let q: Vec<String> = sqlx::query("SELECT cid FROM added_pins")
+        .map(|row: PgRow| row.get("cid"))
+        .fetch_all(pool)
+        .await?;
+    Ok(q.into_iter().collect())
+}
+
+pub async fn remove_pin(cid: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("DELETE FROM added_pins WHERE cid = $1")
+        .bind(cid)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Remembers when an orphaned pin was found first and returns the seconds
+/// since then.
+pub async fn track_orphan(cid: &str, pool: &PgPool) -> Result<i64> {
+    let q: i64 = sqlx::query("INSERT INTO orphan_pins (cid) VALUES ($1) ON CONFLICT (cid) DO UPDATE SET cid = EXCLUDED.cid RETURNING EXTRACT(EPOCH FROM NOW() - first_seen)::bigint AS age")
+        .bind(cid)
+        .map(|row: PgRow| row.get("age"))
+        .fetch_one(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn get_tracked(pool: &PgPool) -> Result<Vec<String>> {
+    let q: Vec<String> = sqlx::query("SELECT cid FROM orphan_pins")
+        .map(|row: PgRow| row.get("cid"))
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn forget(cid: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("DELETE FROM orphan_pins WHERE cid = $1")
+        .bind(cid)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+
+use axum::body::Bytes;
+use tokio::sync::mpsc;
+
+use crate::{error::ServiceError, models::UserIdentifier, outbox, JMServiceInner};
+
+mod source;
+
+/// Stored as the IP of imported memes.
+const IMPORT_IP: &str = "import";
+
+pub struct ImportFile {
+    pub user: String,
+    pub category: Option<String>,
+    pub filename: String,
+    pub timestamp: Option<i64>,
+    pub data: Bytes,
+}
+
+/// Sent by the source for every entry, which is either a file to import or
+/// one it couldn't use, with the reason.
+pub enum SourceEntry {
+    File(ImportFile),
+    Rejected(String, ImportResult),
+}
+
+pub enum ImportResult {
+    Imported,
+    Skipped(String),
+    Failed(String),
+}
+
+#[derive(Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// `user/filename` of every file with what happened to it
+    pub files: Vec<(String, ImportResult)>,
+}
+
+impl JMServiceInner {
+    /// Imports all files of a directory tree or archive. Files directly in a
+    /// user's directory get the default category, files in subdirectories
+    /// the category named like the subdirectory. A file that fails is
+    /// reported in the summary and the import goes on with the next one.
+    pub async fn import(
+        &self,
+        path: PathBuf,
+        default_category: Option<String>,
+    ) -> Result<ImportSummary, ServiceError> {
+        // Files are read one at a time, so archives don't have to fit into
+        // memory.
+        let (tx, mut rx) = mpsc::channel(1);
+        let reader = tokio::task::spawn_blocking(move || source::read(&path, tx));
+
+        let mut summary = ImportSummary::default();
+        while let Some(entry) = rx.recv().await {
+            let (name, result) = match entry {
+                SourceEntry::File(file) => {
+                    let name = format!("{}/{}", file.user, file.filename);
+                    let result = self
+                        .import_file(file, default_category.as_deref())
+                        .await
+                        .unwrap_or_else(|err| ImportResult::Failed(err.to_string()));
+                    (name, result)
+                },
+                SourceEntry::Rejected(name, result) => (name, result),
+            };
+            match result {
+                ImportResult::Imported => summary.imported += 1,
+                ImportResult::Skipped(_) => summary.skipped += 1,
+                ImportResult::Failed(_) => summary.failed += 1,
+            }
+            summary.files.push((name, result));
+        }
+        reader.await.map_err(std::io::Error::other)??;
+        Ok(summary)
+    }
+
+    /// Adds the file to the storage and the database like an upload, which
+    /// queues the Matrix event and the pin. Files the user already has a
+    /// meme with the same name of are skipped.
+    async fn import_file(
+        &self,
+        file: ImportFile,
+        default_category: Option<&str>,
+    ) -> Result<ImportResult, ServiceError> {
+        let category = match file.category.as_deref().or(default_category) {
+            Some(category) => category.to_string(),
+            None => return Ok(ImportResult::Skipped("No category".to_string())),
+        };
+        let category = match self.get_category(&category).await? {
+            Some(category) => category,
+            None => {
+                return Ok(ImportResult::Skipped(format!(
+                    "Category {} not existing",
+                    category
+                )))
+            },
+        };
+        let user = match self.get_user(UserIdentifier::Id(file.user.clone())).await? {
+            Some(user) => user,
+            None => {
+                return Ok(ImportResult::Skipped(format!(
+                    "User {} not existing",
+                    file.user
+                )))
+            },
+        };
+        if self
+            .get_user_meme(user.id.clone(), file.filename.clone())
+            .await?
+            .is_some()
+        {
+            return Ok(ImportResult::Skipped("Already present".to_string()));
+        }
+
+        let stored = self.storage.add(file.data, file.filename).await?;
+        self.add_memes_sql(
+            &user,
+            &[stored],
+            IMPORT_IP,
+            &category,
+            file.timestamp,
+            outbox::ACTION_MATRIX,
+        )
+        .await?;
+        Ok(ImportResult::Imported)
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path},
+    time::UNIX_EPOCH,
+};
+
+use axum::body::Bytes;
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use tokio::sync::mpsc::Sender;
+
+use super::{ImportFile, ImportResult, SourceEntry};
+
+/// Reads all files of a directory tree, a zip or a (gzipped) tar archive and
+/// sends them to the importer. Runs on a blocking thread.
+pub fn read(path: &Path, tx: Sender<SourceEntry>) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if path.is_dir() {
+        read_dir(path, path, &tx)
+    } else if name.ends_with(".zip") {
+        read_zip(File::open(path)?, &tx)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        read_tar(GzDecoder::new(File::open(path)?), &tx)
+    } else if name.ends_with(".tar") {
+        read_tar(File::open(path)?, &tx)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "expected a directory, zip or tar archive",
+        ))
+    }
+}
+
+fn read_dir(root: &Path, dir: &Path, tx: &Sender<SourceEntry>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            read_dir(root, &path, tx)?;
+            continue;
+        }
+        let timestamp = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_secs() as i64);
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        send(relative, timestamp, || fs::read(&path), tx)?;
+    }
+    Ok(())
+}
+
+fn read_zip(file: File, tx: &Sender<SourceEntry>) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = match file.enclosed_name() {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        let time = file.last_modified();
+        let timestamp =
+            NaiveDate::from_ymd_opt(time.year() as i32, time.month() as u32, time.day() as u32)
+                .and_then(|date| {
+                    date.and_hms_opt(
+                        time.hour() as u32,
+                        time.minute() as u32,
+                        time.second() as u32,
+                    )
+                })
+                .map(|time| time.and_utc().timestamp());
+        send(&path, timestamp, || read_all(&mut file), tx)?;
+    }
+    Ok(())
+}
+
+fn read_tar<R: Read>(reader: R, tx: &Sender<SourceEntry>) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let timestamp = entry.header().mtime().ok().map(|time| time as i64);
+        send(&path, timestamp, || read_all(&mut entry), tx)?;
+    }
+    Ok(())
+}
+
+fn read_all<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Maps `<user>/<file>` and `<user>/<category>/<file>` paths to a file,
+/// ignoring other paths and hidden files.
+fn send<F>(path: &Path, timestamp: Option<i64>, read: F, tx: &Sender<SourceEntry>) -> io::Result<()>
+where
+    F: FnOnce() -> io::Result<Vec<u8>>,
+{
+    let parts: Vec<String> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    let (user, category, filename) = match parts.as_slice() {
+        [user, filename] => (user, None, filename),
+        [user, category, filename] => (user, Some(category.clone()), filename),
+        _ => {
+            return reject(
+                path,
+                ImportResult::Skipped("Expected <user>/[<category>/]<file>".to_string()),
+                tx,
+            )
+        },
+    };
+    if filename.starts_with('.') {
+        return Ok(());
+    }
+    let data = match read() {
+        Ok(data) => data,
+        Err(err) => return reject(path, ImportResult::Failed(err.to_string()), tx),
+    };
+    let file = ImportFile {
+        user: user.clone(),
+        category,
+        filename: filename.clone(),
+        timestamp,
+        data: Bytes::from(data),
+    };
+    forward(SourceEntry::File(file), tx)
+}
+
+fn reject(path: &Path, result: ImportResult, tx: &Sender<SourceEntry>) -> io::Result<()> {
+    forward(
+        SourceEntry::Rejected(path.display().to_string(), result),
+        tx,
+    )
+}
+
+fn forward(entry: SourceEntry, tx: &Sender<SourceEntry>) -> io::Result<()> {
+    tx.blocking_send(entry)
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "import stopped"))
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use reqwest::Response;
+use serde::Deserialize;
+use tokio::time::timeout;
+
+use crate::{config::MfsConfig, error::ServiceError, JMService, JMServiceInner};
+
+use super::{sql, IpfsStorage};
+
+/// Files of every user by their name, with their CIDs.
+type MfsTree = BTreeMap<String, HashMap<String, String>>;
+
+#[derive(Deserialize)]
+struct MfsLs {
+    #[serde(rename = "Entries")]
+    entries: Option<Vec<MfsEntry>>,
+}
+
+#[derive(Deserialize)]
+struct MfsEntry {
+    #[serde(rename = "Name")]
+    name: String,
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct MfsStat {
+    #[serde(rename = "Hash")]
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct KeyList {
+    #[serde(rename = "Keys")]
+    keys: Vec<Key>,
+}
+
+#[derive(Deserialize)]
+struct Key {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Published {
+    #[serde(rename = "Name")]
+    name: String,
+}
+
+/// Keeps the MFS tree in sync with the database. It is woken up by
+/// `mfs_notify` after every upload and then only updates the directories of
+/// users whose files changed since the last sync. The whole tree is
+/// reconciled at every sync interval, which also catches files changed in
+/// MFS directly.
+pub async fn run(service: JMService) {
+    let config = match &service.mfs {
+        Some(config) => config,
+        None => return,
+    };
+    let sync_interval = Duration::from_secs(config.sync_interval);
+    let publish_interval = Duration::from_secs(config.publish_interval);
+    let mut published: Option<(String, Instant)> = None;
+    let mut synced: Option<(MfsTree, Instant)> = None;
+    loop {
+        // Time of the last full sync, if it is recent enough
+        let last_full = synced
+            .as_ref()
+            .map(|(_, time)| *time)
+            .filter(|time| time.elapsed() < sync_interval);
+        let previous = synced.as_ref().filter(|_| last_full.is_some());
+        match service
+            .sync_mfs(config, previous.map(|(tree, _)| tree))
+            .await
+        {
+            Ok((root, tree)) => {
+                synced = Some((tree, last_full.unwrap_or_else(Instant::now)));
+                let due = match &published {
+                    Some((cid, time)) => *cid != root && time.elapsed() >= publish_interval,
+                    None => true,
+                };
+                if let (true, Some(key)) = (due, &config.ipns_key) {
+                    match service.publish_mfs(key, &root).await {
+                        Ok(name) => {
+                            eprintln!("Published MFS root {} as /ipns/{}", root, name);
+                            published = Some((root, Instant::now()));
+                        },
+                        Err(err) => eprintln!("IPNS publish error: {}", err),
+                    }
+                }
+            },
+            Err(err) => {
+                eprintln!("MFS sync error: {}", err);
+                synced = None;
+            },
+        }
+        let _ = timeout(sync_interval, service.mfs_notify.notified()).await;
+    }
+}
+
+impl JMServiceInner {
+    /// Syncs the MFS tree with the database and returns its root CID and
+    /// the synced files. Users whose files are the same as in `previous` are
+    /// skipped.
+    async fn sync_mfs(
+        &self,
+        config: &MfsConfig,
+        previous: Option<&MfsTree>,
+    ) -> Result<(String, MfsTree), ServiceError> {
+        let ipfs = self.storage.ipfs().ok_or(ServiceError::NoIpfsNode)?;
+        let mut tree = MfsTree::new();
+        for file in sql::get_mfs_files(&self.db_pool).await? {
+            // Slashes would create subdirectories
+            if !valid_name(&file.user) || !valid_name(&file.filename) {
+                continue;
+            }
+            tree.entry(file.user)
+                .or_default()
+                .insert(file.filename, file.cid);
+        }
+        let root = ipfs.mfs_sync(&config.root, &tree, previous).await?;
+        Ok((root, tree))
+    }
+
+    async fn publish_mfs(&self, key: &str, root: &str) -> Result<String, ServiceError> {
+        let ipfs = self.storage.ipfs().ok_or(ServiceError::NoIpfsNode)?;
+        ipfs.ipns_publish(key, root).await
+    }
+}
+
+/// MFS and IPNS keys are local to a node, so the tree is kept on the first
+/// API node only.
+impl IpfsStorage {
+    async fn mfs_call(
+        &self,
+        command: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Response, ServiceError> {
+        let node = self.nodes.apis().first().ok_or(ServiceError::NoIpfsNode)?;
+        let res = self
+            .client
+            .post(node.url.join(&format!("/api/v0/{}", command))?)
+            .query(query)
+            .send()
+            .await?;
+        if res.status().is_success() {
+            Ok(res)
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    async fn mfs_ls(&self, path: &str) -> Result<Vec<MfsEntry>, ServiceError> {
+        let res = self
+            .mfs_call("files/ls", &[("arg", path), ("long", "true")])
+            .await?;
+        let ls: MfsLs = res.json().await?;
+        Ok(ls.entries.unwrap_or_default())
+    }
+
+    async fn mfs_rm(&self, path: &str) -> Result<(), ServiceError> {
+        self.mfs_call("files/rm", &[("arg", path), ("recursive", "true")])
+            .await?;
+        Ok(())
+    }
+
+    /// Makes the directory `root` contain a directory for every user with
+    /// their files, removing everything else.
+    async fn mfs_sync(
+        &self,
+        root: &str,
+        tree: &MfsTree,
+        previous: Option<&MfsTree>,
+    ) -> Result<String, ServiceError> {
+        self.mfs_call("files/mkdir", &[("arg", root), ("parents", "true")])
+            .await?;
+        for entry in self.mfs_ls(root).await? {
+            if !tree.contains_key(entry.name.as_str()) {
+                self.mfs_rm(&format!("{}/{}", root, entry.name)).await?;
+            }
+        }
+
+        for (user, files) in tree {
+            if previous.and_then(|previous| previous.get(user)) == Some(files) {
+                continue;
+            }
+            let dir = format!("{}/{}", root, user);
+            self.mfs_call("files/mkdir", &[("arg", &dir), ("parents", "true")])
+                .await?;
+            let mut existing = HashMap::new();
+            for entry in self.mfs_ls(&dir).await? {
+                if files.get(&entry.name) == Some(&entry.hash) {
+                    existing.insert(entry.name, entry.hash);
+                } else {
+                    self.mfs_rm(&format!("{}/{}", dir, entry.name)).await?;
+                }
+            }
+            for (filename, cid) in files {
+                if !existing.contains_key(filename) {
+                    let source = format!("/ipfs/{}", cid);
+                    let target = format!("{}/{}", dir, filename);
+                    self.mfs_call("files/cp", &[("arg", &source), ("arg", &target)])
+                        .await?;
+                }
+            }
+        }
+
+        self.mfs_call("files/flush", &[("arg", root)]).await?;
+        let stat: MfsStat = self
+            .mfs_call("files/stat", &[("arg", root)])
+            .await?
+            .json()
+            .await?;
+        Ok(stat.hash)
+    }
+
+    /// Publishes the CID under the IPNS key, which is created if it doesn't
+    /// exist yet. Returns the IPNS name.
+    async fn ipns_publish(&self, key: &str, cid: &str) -> Result<String, ServiceError> {
+        let keys: KeyList = self.mfs_call("key/list", &[]).await?.json().await?;
+        if !keys.keys.iter().any(|k| k.name == key) {
+            self.mfs_call("key/gen", &[("arg", key), ("type", "ed25519")])
+                .await?;
+        }
+        let path = format!("/ipfs/{}", cid);
+        let published: Published = self
+            .mfs_call("name/publish", &[("arg", &path), ("key", key)])
+            .await?
+            .json()
+            .await?;
+        Ok(published.name)
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
@@ -1,13 +1,31 @@
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
+use async_trait::async_trait;
 use axum::body::Bytes;
+use futures_util::{StreamExt, TryStreamExt};
 use reqwest::{
+    header::{HeaderName, ACCEPT, CONTENT_LENGTH, RANGE},
     multipart::{Form, Part},
-    Response,
+    Client, Response, StatusCode, Url,
 };
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::{
+    error::ServiceError,
+    export::{car, CAR_CONTENT_TYPE},
+    storage::{ByteStream, Content, Storage, StoredFile},
+};
 
-use crate::{error::ServiceError, JMServiceInner};
+use self::nodes::{IpfsNode, IpfsNodes};
+
+pub mod mfs;
+pub mod nodes;
+mod sql;
 
 #[derive(Deserialize)]
 pub struct IPFSFile {
@@ -15,13 +33,21 @@ pub struct IPFSFile {
     pub hash: String,
     #[serde(rename = "Name")]
     pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct IPFSStat {
     #[serde(rename = "Size")]
-    pub size: String,
+    pub size: u64,
 }
 
 #[derive(Serialize)]
 pub struct CatQuery {
     pub arg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -34,40 +60,361 @@ pub struct PinQuery {
     pub arg: String,
 }
 
-impl JMServiceInner {
+#[derive(Serialize)]
+pub struct PinLsQuery {
+    pub arg: String,
+    #[serde(rename = "type")]
+    pub pin_type: String,
+}
+
+#[derive(Deserialize)]
+pub struct PinList {
+    #[serde(rename = "Keys")]
+    pub keys: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct IPFSError {
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct StatQuery {
+    pub arg: String,
+}
+
+/// Stores files on IPFS, through the configured API nodes and gateways.
+pub struct IpfsStorage {
+    client: Client,
+    nodes: IpfsNodes,
+}
+
+impl IpfsStorage {
+    pub fn new(client: Client, nodes: IpfsNodes) -> Self {
+        Self { client, nodes }
+    }
+
     pub async fn ipfs_cat(&self, cid: String) -> Result<Response, ServiceError> {
-        let request = self
-            .client
-            .post(self.ipfs_url.join("/api/v0/cat")?)
-            .query(&CatQuery::new(cid));
-        Ok(request.send().await?)
+        let (res, _) = self
+            .ipfs_read(|node| {
+                Ok(if node.gateway {
+                    self.client.get(gateway_url(node, &cid)?)
+                } else {
+                    self.client
+                        .post(node.url.join("/api/v0/cat")?)
+                        .query(&CatQuery::new(cid.clone()))
+                })
+            })
+            .await?;
+        Ok(res)
     }
 
-    pub async fn ipfs_add(&self, file: Bytes, filename: String) -> Result<IPFSFile, ServiceError> {
-        let request = self
-            .client
-            .post(self.ipfs_url.join("/api/v0/add")?)
-            .query(&AddQuery::new(false))
-            .multipart(Form::new().part("file", Part::stream(file).file_name(filename)));
-        let response = request.send().await?;
-        let res: IPFSFile = response.json().await?;
+    pub async fn ipfs_cat_range(
+        &self,
+        cid: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Response, ServiceError> {
+        let (res, node) = self
+            .ipfs_read(|node| {
+                Ok(if node.gateway {
+                    self.client
+                        .get(gateway_url(node, &cid)?)
+                        .header(RANGE, format!("bytes={}-{}", offset, offset + length - 1))
+                } else {
+                    self.client
+                        .post(node.url.join("/api/v0/cat")?)
+                        .query(&CatQuery::range(cid.clone(), offset, length))
+                })
+            })
+            .await?;
+        // A gateway ignoring the range would send the whole file
+        if node.gateway && res.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(ServiceError::InvalidResponse(res.status()));
+        }
         Ok(res)
     }
 
+    pub async fn ipfs_stat(&self, cid: String) -> Result<IPFSStat, ServiceError> {
+        let (res, node) = self
+            .ipfs_read(|node| {
+                Ok(if node.gateway {
+                    self.client.head(gateway_url(node, &cid)?)
+                } else {
+                    self.client
+                        .post(node.url.join("/api/v0/files/stat")?)
+                        .query(&StatQuery::new(cid.clone()))
+                })
+            })
+            .await?;
+        if node.gateway {
+            // The body of a HEAD response is empty, so `content_length` is 0
+            let size = res
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok())
+                .and_then(|length| length.parse().ok())
+                .ok_or(ServiceError::InvalidResponse(res.status()))?;
+            Ok(IPFSStat { size })
+        } else {
+            Ok(res.json().await?)
+        }
+    }
+
+    /// Adds the file to every healthy API node, so it is available even if
+    /// one of them fails before it is pinned.
+    pub async fn ipfs_add(&self, file: Bytes, filename: String) -> Result<IPFSFile, ServiceError> {
+        let mut added: Option<IPFSFile> = None;
+        let mut error = ServiceError::NoIpfsNode;
+        for node in self.nodes.write_nodes() {
+            let request = self
+                .client
+                .post(node.url.join("/api/v0/add")?)
+                .query(&AddQuery::new(false))
+                .multipart(Form::new().part(
+                    "file",
+                    Part::stream(file.clone()).file_name(filename.clone()),
+                ));
+            let res = async {
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    return Err(ServiceError::InvalidResponse(response.status()));
+                }
+                Ok(response.json::<IPFSFile>().await?)
+            }
+            .await;
+            match (res, &added) {
+                (Ok(file), None) => added = Some(file),
+                (Ok(file), Some(first)) if file.hash != first.hash => eprintln!(
+                    "IPFS node {} added {} as {} instead of {}",
+                    node.url, filename, file.hash, first.hash
+                ),
+                (Ok(_), Some(_)) => (),
+                (Err(err), _) => error = err,
+            }
+        }
+        added.ok_or(error)
+    }
+
+    /// Pins the CID on all API nodes. Every node is tried, but it fails if
+    /// any of them couldn't pin it, so that the pin is retried until every
+    /// node has a copy.
     pub async fn ipfs_pin(&self, cid: String) -> Result<(), ServiceError> {
-        let request = self
-            .client
-            .post(self.ipfs_url.join("/api/v0/pin/add")?)
-            .query(&PinQuery::new(cid))
-            .timeout(Duration::from_secs(60));
-        request.send().await?;
+        let mut errors = vec![];
+        for node in self.nodes.apis() {
+            let res = async {
+                let res = self
+                    .client
+                    .post(node.url.join("/api/v0/pin/add")?)
+                    .query(&PinQuery::new(cid.clone()))
+                    .timeout(Duration::from_secs(60))
+                    .send()
+                    .await?;
+                if !res.status().is_success() {
+                    return Err(ServiceError::InvalidResponse(res.status()));
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(err) = res {
+                errors.push(format!("{}: {}", node.url, err));
+            }
+        }
+        nodes_result(errors)
+    }
+
+    /// Lists the CIDs pinned recursively on any API node.
+    pub async fn ipfs_pins(&self) -> Result<HashSet<String>, ServiceError> {
+        let mut pins = HashSet::new();
+        for node in self.nodes.apis() {
+            let res = self
+                .client
+                .post(node.url.join("/api/v0/pin/ls")?)
+                .query(&[("type", "recursive")])
+                .timeout(Duration::from_secs(60))
+                .send()
+                .await?;
+            if !res.status().is_success() {
+                return Err(ServiceError::InvalidResponse(res.status()));
+            }
+            let list: PinList = res.json().await?;
+            pins.extend(list.keys.into_keys());
+        }
+        Ok(pins)
+    }
+
+    /// Unpins the CID on all API nodes it is pinned on, trying every node even
+    /// if some of them fail.
+    pub async fn ipfs_unpin(&self, cid: String) -> Result<(), ServiceError> {
+        let mut errors = vec![];
+        for node in self.nodes.apis() {
+            let res = async {
+                let res = self
+                    .client
+                    .post(node.url.join("/api/v0/pin/rm")?)
+                    .query(&PinQuery::new(cid.clone()))
+                    .send()
+                    .await?;
+                if !res.status().is_success() {
+                    let status = res.status();
+                    let err: IPFSError = res
+                        .json()
+                        .await
+                        .map_err(|_| ServiceError::InvalidResponse(status))?;
+                    if !err.message.contains("not pinned") {
+                        return Err(ServiceError::InvalidResponse(status));
+                    }
+                }
+                Ok(())
+            }
+            .await;
+            if let Err(err) = res {
+                errors.push(format!("{}: {}", node.url, err));
+            }
+        }
+        nodes_result(errors)
+    }
+
+    /// Checks whether the CID is pinned recursively on all API nodes.
+    pub async fn ipfs_is_pinned(&self, cid: String) -> Result<bool, ServiceError> {
+        for node in self.nodes.apis() {
+            let request = self
+                .client
+                .post(node.url.join("/api/v0/pin/ls")?)
+                .query(&PinLsQuery::new(cid.clone()));
+            let res = request.send().await?;
+            if !res.status().is_success() {
+                // IPFS answers with an error if the CID is not pinned
+                let status = res.status();
+                let err: IPFSError = res
+                    .json()
+                    .await
+                    .map_err(|_| ServiceError::InvalidResponse(status))?;
+                if err.message.contains("not pinned") {
+                    return Ok(false);
+                }
+                return Err(ServiceError::InvalidResponse(status));
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Storage for IpfsStorage {
+    async fn add(&self, file: Bytes, filename: String) -> Result<StoredFile, ServiceError> {
+        // The size reported by IPFS is the one of the DAG, not of the file
+        let size = file.len() as i64;
+        let res = self.ipfs_add(file, filename).await?;
+        Ok(StoredFile {
+            cid: res.hash,
+            name: res.name,
+            size,
+        })
+    }
+
+    async fn cat(&self, cid: &str) -> Result<Content, ServiceError> {
+        let res = self.ipfs_cat(cid.to_string()).await?;
+        // The API sends the length in its own header, gateways send a normal one
+        let length = res
+            .headers()
+            .get(HeaderName::from_static("x-content-length"))
+            .or_else(|| res.headers().get(CONTENT_LENGTH))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        Ok(Content {
+            length,
+            stream: res.bytes_stream().map_err(ServiceError::from).boxed(),
+        })
+    }
+
+    async fn cat_range(
+        &self,
+        cid: &str,
+        offset: u64,
+        length: u64,
+    ) -> Result<Content, ServiceError> {
+        let res = self.ipfs_cat_range(cid.to_string(), offset, length).await?;
+        Ok(Content {
+            length: Some(length),
+            stream: res.bytes_stream().map_err(ServiceError::from).boxed(),
+        })
+    }
+
+    async fn size(&self, cid: &str) -> Result<u64, ServiceError> {
+        Ok(self.ipfs_stat(cid.to_string()).await?.size)
+    }
+
+    async fn pin(&self, cid: &str) -> Result<(), ServiceError> {
+        self.ipfs_pin(cid.to_string()).await
+    }
+
+    async fn is_pinned(&self, cid: &str) -> Result<bool, ServiceError> {
+        self.ipfs_is_pinned(cid.to_string()).await
+    }
+
+    async fn check_health(&self) {
+        self.check_nodes().await
+    }
+
+    /// Exports the DAG from IPFS and strips the header of the CAR.
+    async fn car_blocks(&self, cid: &str) -> Result<ByteStream, ServiceError> {
+        let (res, _) = self
+            .ipfs_read(|node| {
+                Ok(if node.gateway {
+                    self.client
+                        .get(gateway_url(node, cid)?)
+                        .query(&[("format", "car")])
+                        .header(ACCEPT, CAR_CONTENT_TYPE)
+                } else {
+                    self.client
+                        .post(node.url.join("/api/v0/dag/export")?)
+                        .query(&[("arg", cid)])
+                })
+            })
+            .await?;
+        let mut reader =
+            StreamReader::new(res.bytes_stream().map_err(std::io::Error::other).boxed());
+        let header = car::read_varint(&mut reader).await?;
+        tokio::io::copy(&mut (&mut reader).take(header), &mut tokio::io::sink()).await?;
+        Ok(ReaderStream::new(reader)
+            .map_err(ServiceError::from)
+            .boxed())
+    }
+
+    fn ipfs(&self) -> Option<&IpfsStorage> {
+        Some(self)
+    }
+}
+
+fn gateway_url(node: &IpfsNode, cid: &str) -> Result<Url, ServiceError> {
+    Ok(node.url.join(&format!("/ipfs/{}", cid))?)
+}
+
+fn nodes_result(errors: Vec<String>) -> Result<(), ServiceError> {
+    if errors.is_empty() {
         Ok(())
+    } else {
+        Err(ServiceError::IpfsNodes(errors.join(", ")))
     }
 }
 
 impl CatQuery {
     pub fn new(cid: String) -> Self {
-        Self { arg: cid }
+        Self {
+            arg: cid,
+            offset: None,
+            length: None,
+        }
+    }
+
+    pub fn range(cid: String, offset: u64, length: u64) -> Self {
+        Self {
+            arg: cid,
+            offset: Some(offset),
+            length: Some(length),
+        }
     }
 }
 
@@ -82,3 +429,20 @@ impl PinQuery {
         Self { arg: cid }
     }
 }
+
+impl PinLsQuery {
+    pub fn new(cid: String) -> Self {
+        Self {
+            arg: cid,
+            pin_type: "recursive".to_string(),
+        }
+    }
+}
+
+impl StatQuery {
+    pub fn new(cid: String) -> Self {
+        Self {
+            arg: format!("/ipfs/{}", cid),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use reqwest::{RequestBuilder, Response, Url};
+
+use crate::error::ServiceError;
+
+use super::IpfsStorage;
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Identity CID of an empty block, which every gateway can serve without
+/// fetching anything from the network.
+const EMPTY_CID: &str = "bafkqaaa";
+
+pub struct IpfsNode {
+    pub url: Url,
+    pub gateway: bool,
+    healthy: AtomicBool,
+}
+
+/// The configured IPFS API nodes and read-only HTTP gateways.
+pub struct IpfsNodes {
+    apis: Vec<IpfsNode>,
+    gateways: Vec<IpfsNode>,
+}
+
+impl IpfsNode {
+    fn new(url: Url, gateway: bool) -> Self {
+        Self {
+            url,
+            gateway,
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+}
+
+impl IpfsNodes {
+    pub fn new(apis: Vec<Url>, gateways: Vec<Url>) -> Self {
+        Self {
+            apis: apis
+                .into_iter()
+                .map(|url| IpfsNode::new(url, false))
+                .collect(),
+            gateways: gateways
+                .into_iter()
+                .map(|url| IpfsNode::new(url, true))
+                .collect(),
+        }
+    }
+
+    pub fn apis(&self) -> &[IpfsNode] {
+        &self.apis
+    }
+
+    /// Returns the nodes to read from in the order they should be tried:
+    /// healthy API nodes, healthy gateways and then all unhealthy nodes, in
+    /// case the last health check is outdated.
+    fn read_order(&self) -> Vec<&IpfsNode> {
+        let mut nodes: Vec<&IpfsNode> = self.apis.iter().chain(self.gateways.iter()).collect();
+        nodes.sort_by_key(|node| !node.is_healthy());
+        nodes
+    }
+
+    /// Returns the healthy API nodes, or all of them if none is healthy.
+    pub(super) fn write_nodes(&self) -> Vec<&IpfsNode> {
+        let healthy: Vec<&IpfsNode> = self.apis.iter().filter(|node| node.is_healthy()).collect();
+        if healthy.is_empty() {
+            self.apis.iter().collect()
+        } else {
+            healthy
+        }
+    }
+}
+
+impl IpfsStorage {
+    /// Checks the health of all IPFS nodes.
+    pub(super) async fn check_nodes(&self) {
+        for node in self.nodes.apis.iter().chain(self.nodes.gateways.iter()) {
+            let healthy = self.check_node(node).await.is_ok();
+            if healthy != node.is_healthy() {
+                eprintln!(
+                    "IPFS node {} is {}",
+                    node.url,
+                    if healthy { "healthy" } else { "unhealthy" }
+                );
+            }
+            node.set_healthy(healthy);
+        }
+    }
+
+    async fn check_node(&self, node: &IpfsNode) -> Result<(), ServiceError> {
+        let request = if node.gateway {
+            self.client
+                .head(node.url.join(&format!("/ipfs/{}", EMPTY_CID))?)
+        } else {
+            self.client.post(node.url.join("/api/v0/version")?)
+        };
+        let res = request.timeout(HEALTH_CHECK_TIMEOUT).send().await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    /// Sends a read request to the first node that answers successfully.
+    /// Nodes that can't be reached are marked as unhealthy, error responses
+    /// are not, since they are usually caused by the requested content.
+    pub(super) async fn ipfs_read<F>(
+        &self,
+        request: F,
+    ) -> Result<(Response, &IpfsNode), ServiceError>
+    where
+        F: Fn(&IpfsNode) -> Result<RequestBuilder, ServiceError>,
+    {
+        let mut error = ServiceError::NoIpfsNode;
+        for node in self.nodes.read_order() {
+            match request(node)?.send().await {
+                Ok(res) if res.status().is_success() => {
+                    node.set_healthy(true);
+                    return Ok((res, node));
+                },
+                Ok(res) => error = ServiceError::InvalidResponse(res.status()),
+                Err(err) => {
+                    if err.is_connect() || err.is_timeout() {
+                        node.set_healthy(false);
+                    }
+                    error = err.into();
+                },
+            }
+        }
+        Err(error)
+    }
+}
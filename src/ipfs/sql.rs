@@ -0,0 +1,20 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+pub struct MfsFile {
+    pub user: String,
+    pub filename: String,
+    pub cid: String,
+}
+
+/// Lists the latest version of every file of every user.
+pub async fn get_mfs_files(pool: &PgPool) -> Result<Vec<MfsFile>> {
+    let q: Vec<MfsFile> = sqlx::query("SELECT DISTINCT ON (userid, filename) userid, filename, cid FROM memes ORDER BY userid, filename, id DESC")
+        .map(|row: PgRow| MfsFile {
+            user: row.get("userid"),
+            filename: row.get("filename"),
+            cid: row.get("cid"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
@@ -1,4 +0,0 @@
-mod error;
-mod ipheader;
-
-pub use ipheader::ExtractIP;
@@ -1,24 +1,42 @@
 use axum::{
     body::Body,
+    handler::get,
     http::{header, HeaderValue, Request},
     Router,
 };
-use config::Config;
+use cache::DiskCache;
+use cli::Command;
+use config::{Config, GcConfig, MfsConfig};
 use error::JMError;
-use reqwest::{Client, Url};
+use matrix::Matrix;
+use reqwest::Client;
 use sqlx::PgPool;
 use std::{path::PathBuf, sync::Arc};
+use storage::Storage;
 use structopt::StructOpt;
+use tokio::sync::{Mutex, Notify};
 use tower_http::{add_extension::AddExtensionLayer, set_header::SetResponseHeaderLayer};
 
+mod cache;
 mod cdn;
+mod check;
+mod cli;
 mod config;
 mod error;
+mod export;
+mod extract;
+mod gallery;
+mod gc;
+mod import;
 mod ipfs;
-mod lib;
 mod matrix;
+mod media;
+mod migrate;
 mod models;
+mod outbox;
+mod rebuild;
 mod sql;
+mod storage;
 mod v1;
 mod v2;
 
@@ -31,17 +49,26 @@ struct Opt {
         default_value = "./config.toml"
     )]
     config: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
 }
 
 pub struct JMServiceInner {
     client: Client,
     db_pool: PgPool,
-    ipfs_url: Url,
+    storage: Box<dyn Storage>,
     int_cdn: String,
     ext_cdn: String,
-    matrix_url: Url,
-    matrix_token: String,
-    matrix_domain: String,
+    public_url: String,
+    matrix: Option<Matrix>,
+    admins: Vec<String>,
+    outbox_notify: Notify,
+    cache: Option<DiskCache>,
+    check_lock: Arc<Mutex<()>>,
+    mfs: Option<MfsConfig>,
+    mfs_notify: Notify,
+    profile_notify: Notify,
+    gc: Option<GcConfig>,
 }
 
 pub type JMService = Arc<JMServiceInner>;
@@ -55,10 +82,30 @@ async fn main() -> Result<(), JMError> {
     let db_pool = PgPool::new(&config.database).await?;
     let service = config.service(db_pool)?;
 
+    if let Some(command) = opt.command {
+        return cli::run(command, service).await;
+    }
+
+    tokio::spawn(outbox::run(service.clone()));
+    tokio::spawn(storage::run_health_checks(service.clone()));
+    tokio::spawn(ipfs::mfs::run(service.clone()));
+    tokio::spawn(gc::run(service.clone()));
+    tokio::spawn(matrix::profile::run(service.clone()));
+
     let app = Router::new()
         .nest("/api/v1", v1::routes())
         .nest("/api/v2", v2::routes())
         .nest("/cdn", cdn::routes())
+        .nest("/_matrix/app/v1", matrix::appservice::routes());
+    // The gallery links to itself in OpenGraph tags and oEmbed responses
+    let app = if service.public_url().is_empty() {
+        app.boxed()
+    } else {
+        app.nest(gallery::BASE, gallery::routes())
+            .route("/oembed", get(gallery::oembed))
+            .boxed()
+    };
+    let app = app
         .layer(AddExtensionLayer::new(service))
         .layer(SetResponseHeaderLayer::<_, Request<Body>>::if_not_present(
             header::ACCESS_CONTROL_ALLOW_ORIGIN,
@@ -0,0 +1,50 @@
+use std::convert::Infallible;
+
+use axum::{
+    body::{Bytes, Full},
+    response::IntoResponse,
+    Json,
+};
+use reqwest::StatusCode;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::error::ServiceError;
+
+#[derive(Error, Debug)]
+pub enum AppserviceError {
+    #[error("No hs_token provided")]
+    Unauthorized,
+    #[error("Invalid hs_token")]
+    Forbidden,
+    #[error("SQL error: {0}")]
+    Sql(#[from] sqlx::Error),
+    #[error("JMService error: {0}")]
+    Service(#[from] ServiceError),
+}
+
+/// Error body of the Matrix specification.
+#[derive(Serialize)]
+struct MatrixError {
+    errcode: &'static str,
+    error: String,
+}
+
+impl IntoResponse for AppserviceError {
+    type Body = Full<Bytes>;
+
+    type BodyError = Infallible;
+
+    fn into_response(self) -> axum::http::Response<Self::Body> {
+        let (status, errcode) = match self {
+            AppserviceError::Unauthorized => (StatusCode::UNAUTHORIZED, "M_UNAUTHORIZED"),
+            AppserviceError::Forbidden => (StatusCode::FORBIDDEN, "M_FORBIDDEN"),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, "M_UNKNOWN"),
+        };
+        let body = MatrixError {
+            errcode,
+            error: self.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{Extension, FromRequest, Path, Query, RequestParts},
+    handler::put,
+    response::IntoResponse,
+    routing::BoxRoute,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{error::ServiceError, JMService, JMServiceInner};
+
+pub use error::AppserviceError;
+
+mod error;
+mod sql;
+
+/// Events the homeserver pushes to the application service.
+#[derive(Deserialize)]
+pub struct Transaction {
+    #[serde(default)]
+    pub events: Vec<Event>,
+}
+
+#[derive(Deserialize)]
+pub struct Event {
+    pub event_id: String,
+    pub room_id: String,
+    pub sender: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub content: Value,
+    pub state_key: Option<String>,
+}
+
+/// Registration file telling the homeserver how to reach the application
+/// service and which users and aliases it is interested in.
+#[derive(Serialize)]
+pub struct Registration {
+    pub id: String,
+    pub url: String,
+    pub as_token: String,
+    pub hs_token: String,
+    pub sender_localpart: String,
+    pub rate_limited: bool,
+    pub namespaces: Namespaces,
+}
+
+#[derive(Serialize)]
+pub struct Namespaces {
+    pub users: Vec<Namespace>,
+    pub aliases: Vec<Namespace>,
+    pub rooms: Vec<Namespace>,
+}
+
+#[derive(Serialize)]
+pub struct Namespace {
+    pub exclusive: bool,
+    pub regex: String,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+/// Guards a route by the `hs_token` the homeserver authenticates with, sent
+/// either in the `Authorization` header or the legacy `access_token` query
+/// parameter.
+pub struct Homeserver;
+
+#[async_trait]
+impl<B> FromRequest<B> for Homeserver
+where
+    B: Send,
+{
+    type Rejection = AppserviceError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(service) = Extension::<JMService>::from_request(req)
+            .await
+            .map_err(|_| AppserviceError::Forbidden)?;
+        let header = req
+            .headers()
+            .and_then(|headers| headers.get("authorization"))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let query = Query::<AccessTokenQuery>::from_request(req)
+            .await
+            .ok()
+            .and_then(|Query(query)| query.access_token);
+        let token = header.or(query).ok_or(AppserviceError::Unauthorized)?;
+        match service
+            .matrix
+            .as_ref()
+            .and_then(|matrix| matrix.hs_token.as_ref())
+        {
+            Some(hs_token) if *hs_token == token => Ok(Self),
+            _ => Err(AppserviceError::Forbidden),
+        }
+    }
+}
+
+async fn put_transaction(
+    _: Homeserver,
+    Path(txn_id): Path<String>,
+    Json(transaction): Json<Transaction>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, AppserviceError> {
+    if !sql::has_transaction(&txn_id, &service.db_pool).await? {
+        service.handle_transaction(transaction).await?;
+        sql::add_transaction(&txn_id, &service.db_pool).await?;
+    }
+    Ok(Json(json!({})))
+}
+
+pub fn routes() -> Router<BoxRoute> {
+    Router::new()
+        .route("/transactions/:txn_id", put(put_transaction))
+        .boxed()
+}
+
+impl JMServiceInner {
+    /// Passes the events other users sent to `handle_event`. A failing event
+    /// is only logged, so that the homeserver doesn't retry the whole
+    /// transaction forever.
+    async fn handle_transaction(&self, transaction: Transaction) -> Result<(), ServiceError> {
+        if transaction.events.is_empty() {
+            return Ok(());
+        }
+        let room_id = self.room_id().await?;
+        for event in transaction.events {
+            if self.is_puppet(&event.sender)? {
+                continue;
+            }
+            if let Err(err) = self.handle_event(&event, event.room_id == room_id).await {
+                eprintln!("Handling Matrix event {} failed: {}", event.event_id, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reacts to an event another user sent. Media is only ingested from
+    /// the meme room, while the bot answers commands in every room it was
+    /// invited to.
+    async fn handle_event(&self, event: &Event, meme_room: bool) -> Result<(), ServiceError> {
+        match event.event_type.as_str() {
+            "m.room.member" => {
+                let bot = self.matrix()?.bot_id();
+                if event.state_key.as_deref() == Some(bot.user_id.as_str())
+                    && event.content["membership"] == "invite"
+                {
+                    self.accept_invite(&event.room_id).await
+                } else {
+                    Ok(())
+                }
+            },
+            "m.room.message" => match event.content["msgtype"].as_str() {
+                Some("m.text") => self.handle_command(event).await,
+                Some("m.image") | Some("m.video") if meme_room => self.ingest_media(event).await,
+                _ => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the user is the sender or one of the users of the application
+    /// service, whose events the server sent itself.
+    fn is_puppet(&self, user_id: &str) -> Result<bool, ServiceError> {
+        let matrix = self.matrix()?;
+        let localpart = user_id
+            .strip_prefix('@')
+            .and_then(|user| user.strip_suffix(format!(":{}", matrix.domain).as_str()));
+        Ok(match localpart {
+            Some(localpart) => {
+                localpart == matrix.sender || localpart.starts_with(&matrix.user_prefix)
+            },
+            None => false,
+        })
+    }
+
+    /// Builds the registration of the application service, which the
+    /// homeserver reaches at `url`.
+    pub fn registration(
+        &self,
+        url: String,
+        hs_token: String,
+    ) -> Result<Registration, ServiceError> {
+        let matrix = self.matrix()?;
+        Ok(Registration {
+            id: matrix.as_id.clone(),
+            url,
+            as_token: matrix.token.clone(),
+            hs_token,
+            sender_localpart: matrix.sender.clone(),
+            rate_limited: false,
+            namespaces: Namespaces {
+                users: vec![Namespace {
+                    exclusive: true,
+                    regex: format!(
+                        "@{}.*:{}",
+                        escape_regex(&matrix.user_prefix),
+                        escape_regex(&matrix.domain)
+                    ),
+                }],
+                aliases: vec![Namespace {
+                    exclusive: false,
+                    regex: escape_regex(&matrix.room),
+                }],
+                rooms: vec![],
+            },
+        })
+    }
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,17 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+pub async fn has_transaction(txn_id: &str, pool: &PgPool) -> Result<bool> {
+    sqlx::query("SELECT EXISTS(SELECT 1 FROM appservice_transactions WHERE txn_id = $1) AS seen")
+        .bind(txn_id)
+        .map(|row: PgRow| row.get("seen"))
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn add_transaction(txn_id: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO appservice_transactions (txn_id) VALUES ($1) ON CONFLICT DO NOTHING")
+        .bind(txn_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
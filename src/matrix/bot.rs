@@ -0,0 +1,203 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ServiceError,
+    models::{Meme, MemeOptions},
+    JMServiceInner,
+};
+
+use super::{appservice::Event, RoomID};
+
+const USAGE: &str = "Usage: !meme random [category], !meme search <text> or !meme user <name>";
+
+#[derive(Debug, PartialEq)]
+enum BotCommand {
+    Random(Option<String>),
+    Search(String),
+    User(String),
+    Help,
+}
+
+#[derive(Deserialize)]
+struct TextContent {
+    body: String,
+}
+
+#[derive(Serialize)]
+struct Notice<'a> {
+    msgtype: &'static str,
+    body: &'a str,
+}
+
+impl BotCommand {
+    fn parse(body: &str) -> Option<Self> {
+        let rest = body.trim().strip_prefix("!meme")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let rest = rest.trim();
+        let (command, argument) = match rest.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (rest, ""),
+        };
+        Some(match (command, argument) {
+            ("random", "") => BotCommand::Random(None),
+            ("random", category) => BotCommand::Random(Some(category.to_string())),
+            ("search", text) if !text.is_empty() => BotCommand::Search(text.to_string()),
+            ("user", name) if !name.is_empty() => BotCommand::User(name.to_string()),
+            _ => BotCommand::Help,
+        })
+    }
+}
+
+impl JMServiceInner {
+    /// Answers a `!meme` command with a matching meme.
+    pub(super) async fn handle_command(&self, event: &Event) -> Result<(), ServiceError> {
+        let content: TextContent = match serde_json::from_value(event.content.clone()) {
+            Ok(content) => content,
+            Err(_) => return Ok(()),
+        };
+        let command = match BotCommand::parse(&content.body) {
+            Some(command) => command,
+            None => return Ok(()),
+        };
+        let meme = match command {
+            BotCommand::Random(category) => {
+                self.pick_meme(MemeOptions {
+                    category,
+                    ..MemeOptions::empty()
+                })
+                .await?
+            },
+            BotCommand::Search(search) => {
+                self.pick_meme(MemeOptions {
+                    search: Some(search),
+                    ..MemeOptions::empty()
+                })
+                .await?
+            },
+            BotCommand::User(username) => {
+                self.pick_meme(MemeOptions {
+                    username: Some(username),
+                    ..MemeOptions::empty()
+                })
+                .await?
+            },
+            BotCommand::Help => return self.send_notice(event, USAGE).await,
+        };
+        match meme {
+            Some(meme) => self.send_meme(event, &meme).await,
+            None => self.send_notice(event, "No meme found").await,
+        }
+    }
+
+    async fn pick_meme(&self, filter: MemeOptions) -> Result<Option<Meme>, ServiceError> {
+        match self.get_random_meme(filter).await {
+            Ok(meme) => Ok(Some(meme)),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Joins a room the bot was invited to.
+    pub(super) async fn accept_invite(&self, room_id: &str) -> Result<(), ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!("/_matrix/client/r0/rooms/{}/join", room_id);
+        let res = self
+            .client
+            .post(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .json(&serde_json::json!({}))
+            .send()
+            .await?;
+        if res.status().is_success() {
+            let _: RoomID = res.json().await?;
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    async fn send_meme(&self, event: &Event, meme: &Meme) -> Result<(), ServiceError> {
+        let content = self
+            .media_message(&meme.userid, &meme.filename, &meme.ipfs)
+            .await?;
+        self.send_message(event, &content).await
+    }
+
+    async fn send_notice(&self, event: &Event, body: &str) -> Result<(), ServiceError> {
+        let notice = Notice {
+            msgtype: "m.notice",
+            body,
+        };
+        self.send_message(event, &notice).await
+    }
+
+    /// Sends a message as the bot into the room of the event it answers,
+    /// with a transaction ID derived from the event so that retried
+    /// transactions don't answer twice.
+    async fn send_message<T: Serialize>(
+        &self,
+        event: &Event,
+        content: &T,
+    ) -> Result<(), ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/rooms/{}/send/m.room.message/{}",
+            event.room_id,
+            urlencoding::encode(&event.event_id)
+        );
+        let res = self
+            .client
+            .put(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .json(content)
+            .send()
+            .await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            BotCommand::parse("!meme random"),
+            Some(BotCommand::Random(None))
+        );
+        assert_eq!(
+            BotCommand::parse("  !meme random uff "),
+            Some(BotCommand::Random(Some("uff".to_string())))
+        );
+        assert_eq!(
+            BotCommand::parse("!meme search  funny cat"),
+            Some(BotCommand::Search("funny cat".to_string()))
+        );
+        assert_eq!(
+            BotCommand::parse("!meme user alec"),
+            Some(BotCommand::User("alec".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_incomplete_commands_as_help() {
+        assert_eq!(BotCommand::parse("!meme"), Some(BotCommand::Help));
+        assert_eq!(BotCommand::parse("!meme search"), Some(BotCommand::Help));
+        assert_eq!(BotCommand::parse("!meme user "), Some(BotCommand::Help));
+        assert_eq!(BotCommand::parse("!meme dance"), Some(BotCommand::Help));
+    }
+
+    #[test]
+    fn ignore_other_messages() {
+        assert_eq!(BotCommand::parse("hello"), None);
+        assert_eq!(BotCommand::parse("!memes random"), None);
+        assert_eq!(BotCommand::parse("look: !meme random"), None);
+    }
+}
@@ -0,0 +1,184 @@
+use axum::body::Bytes;
+use futures_util::StreamExt;
+use serde::Deserialize;
+
+use crate::{
+    error::ServiceError,
+    models::{Category, UserIdentifier},
+    outbox,
+    v1::{MAX_UPLOAD_SIZE, UPLOAD_LIMIT},
+    JMServiceInner,
+};
+
+use super::appservice::Event;
+
+/// Stored as the IP of memes posted in the Matrix room.
+pub const MATRIX_IP: &str = "matrix";
+
+/// Content of an `m.image` or `m.video` message.
+#[derive(Deserialize)]
+pub struct MediaContent {
+    pub body: String,
+    pub filename: Option<String>,
+    pub url: Option<String>,
+}
+
+impl MediaContent {
+    /// Clients sending a caption put it in the body and the name of the file
+    /// in `filename`.
+    pub fn filename(&self) -> &str {
+        self.filename.as_deref().unwrap_or(&self.body)
+    }
+
+    fn caption(&self) -> Option<&str> {
+        self.filename.as_ref().map(|_| self.body.as_str())
+    }
+
+    /// Category set by a `!meme category <id>` line in the caption.
+    pub fn category(&self) -> Option<&str> {
+        self.caption()?.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next(), words.next()) {
+                (Some("!meme"), Some("category"), category) => category,
+                _ => None,
+            }
+        })
+    }
+}
+
+impl JMServiceInner {
+    /// Adds an image or video posted in the meme room as a meme of the
+    /// JensMemes user linked to the sender. The outbox only sends the meme
+    /// event, as the media is in the room already.
+    pub(super) async fn ingest_media(&self, event: &Event) -> Result<(), ServiceError> {
+        let matrix = self.matrix()?;
+        let content: MediaContent = match serde_json::from_value(event.content.clone()) {
+            Ok(content) => content,
+            Err(_) => return Ok(()),
+        };
+        let mxc = match &content.url {
+            Some(url) => url,
+            // Encrypted media can't be ingested
+            None => return Ok(()),
+        };
+        let user = match self
+            .get_user(UserIdentifier::Matrix(event.sender.clone()))
+            .await?
+        {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+        if user.dayuploads >= UPLOAD_LIMIT {
+            eprintln!(
+                "{} reached the upload limit, ignoring {}",
+                user.id, event.event_id
+            );
+            return Ok(());
+        }
+        // A category from the caption that doesn't exist falls back to the
+        // default one
+        let mut category: Option<Category> = None;
+        for id in content
+            .category()
+            .into_iter()
+            .chain(matrix.category.as_deref())
+        {
+            category = self.get_category(&id.to_string()).await?;
+            if category.is_some() {
+                break;
+            }
+        }
+        let category = match category {
+            Some(category) => category,
+            None => return Ok(()),
+        };
+
+        let filename = content.filename().replace('/', "_");
+        let data = match self.download_media(mxc).await {
+            Err(ServiceError::TooLarge(_)) => {
+                eprintln!("{} is too large, ignoring {}", mxc, event.event_id);
+                return Ok(());
+            },
+            data => data?,
+        };
+        let file = self.storage.add(data, filename).await?;
+        self.add_memes_sql(
+            &user,
+            &[file],
+            MATRIX_IP,
+            &category,
+            None,
+            outbox::ACTION_MATRIX_EVENT,
+        )
+        .await?;
+        self.outbox_notify.notify_one();
+        self.mfs_notify.notify_one();
+        Ok(())
+    }
+
+    /// Downloads the content of an `mxc://<server>/<media>` URI, which may be
+    /// as large as an upload.
+    pub async fn download_media(&self, mxc: &str) -> Result<Bytes, ServiceError> {
+        let matrix = self.matrix()?;
+        let media = mxc
+            .strip_prefix("mxc://")
+            .ok_or_else(|| ServiceError::NotFound(mxc.to_string()))?;
+        let path = format!("/_matrix/media/r0/download/{}", media);
+        let res = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(ServiceError::InvalidResponse(res.status()));
+        }
+        if res.content_length().unwrap_or(0) > MAX_UPLOAD_SIZE {
+            return Err(ServiceError::TooLarge(MAX_UPLOAD_SIZE));
+        }
+        // The length may be missing, so it is checked while reading as well
+        let mut data = vec![];
+        let mut stream = res.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            if (data.len() + chunk.len()) as u64 > MAX_UPLOAD_SIZE {
+                return Err(ServiceError::TooLarge(MAX_UPLOAD_SIZE));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn content(value: serde_json::Value) -> MediaContent {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn category_from_caption() {
+        let captioned = content(json!({
+            "body": "look at this\n!meme category cringe",
+            "filename": "cat.png",
+        }));
+        assert_eq!(captioned.filename(), "cat.png");
+        assert_eq!(captioned.category(), Some("cringe"));
+    }
+
+    #[test]
+    fn no_category_without_caption() {
+        // Without a filename the body is the name of the file
+        let uncaptioned = content(json!({ "body": "!meme category cringe" }));
+        assert_eq!(uncaptioned.filename(), "!meme category cringe");
+        assert_eq!(uncaptioned.category(), None);
+        let other = content(json!({ "body": "just a caption", "filename": "cat.png" }));
+        assert_eq!(other.category(), None);
+        let incomplete = content(json!({ "body": "!meme category", "filename": "cat.png" }));
+        assert_eq!(incomplete.category(), None);
+    }
+}
@@ -0,0 +1,147 @@
+use reqwest::{header::CONTENT_TYPE, Body};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::ServiceError, JMServiceInner};
+
+use super::sql;
+
+#[derive(Deserialize)]
+struct Upload {
+    content_uri: String,
+}
+
+/// Message clients render as the meme, with the CDN link as its caption.
+#[derive(Serialize)]
+pub struct MediaMessage {
+    pub msgtype: &'static str,
+    pub body: String,
+    pub filename: String,
+    pub url: String,
+    pub external_url: String,
+    pub info: MediaInfo,
+}
+
+#[derive(Serialize)]
+pub struct MediaInfo {
+    pub mimetype: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnail_info: Option<ThumbnailInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ThumbnailInfo {
+    pub mimetype: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub w: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub h: Option<u32>,
+}
+
+impl JMServiceInner {
+    /// Builds an `m.image`, `m.video` or `m.file` message of a meme. Images
+    /// get the thumbnail of the gallery, which is the image itself if it is
+    /// small enough already.
+    pub(super) async fn media_message(
+        &self,
+        user_id: &str,
+        filename: &str,
+        cid: &str,
+    ) -> Result<MediaMessage, ServiceError> {
+        let mime = new_mime_guess::from_path(filename).first_or_octet_stream();
+        let msgtype = match mime.type_().as_str() {
+            "image" => "m.image",
+            "video" => "m.video",
+            _ => "m.file",
+        };
+        let (url, size) = self.upload_media(cid, filename).await?;
+        let (dimensions, thumbnail) = match msgtype {
+            "m.image" => (
+                self.probe_dimensions(cid.to_string()).await?,
+                Some(self.thumbnail_info(cid, filename).await?),
+            ),
+            _ => (None, None),
+        };
+        let (thumbnail_url, thumbnail_info) = thumbnail.unzip();
+        let link = format!("{}/{}/{}", self.ext_cdn_url(), user_id, filename);
+        Ok(MediaMessage {
+            msgtype,
+            body: link.clone(),
+            filename: filename.to_string(),
+            url,
+            external_url: link,
+            info: MediaInfo {
+                mimetype: mime.to_string(),
+                size,
+                w: dimensions.map(|(w, _)| w),
+                h: dimensions.map(|(_, h)| h),
+                thumbnail_url,
+                thumbnail_info,
+            },
+        })
+    }
+
+    async fn thumbnail_info(
+        &self,
+        cid: &str,
+        filename: &str,
+    ) -> Result<(String, ThumbnailInfo), ServiceError> {
+        let (cid, filename) = self.thumbnail(cid, filename).await?;
+        let (url, size) = self.upload_media(&cid, &filename).await?;
+        let dimensions = self.probe_dimensions(cid).await?;
+        Ok((
+            url,
+            ThumbnailInfo {
+                mimetype: new_mime_guess::from_path(&filename)
+                    .first_or_octet_stream()
+                    .to_string(),
+                size,
+                w: dimensions.map(|(w, _)| w),
+                h: dimensions.map(|(_, h)| h),
+            },
+        ))
+    }
+
+    /// Uploads a file to the media repository of the homeserver once and
+    /// returns its content URI.
+    pub(super) async fn upload_media(
+        &self,
+        cid: &str,
+        filename: &str,
+    ) -> Result<(String, Option<u64>), ServiceError> {
+        let size = self.storage.size(cid).await.ok();
+        if let Some(mxc) = sql::get_media(cid, &self.db_pool).await? {
+            return Ok((mxc, size));
+        }
+        let matrix = self.matrix()?;
+        let content = self.storage.cat(cid).await?;
+        let mime = new_mime_guess::from_path(filename).first_or_octet_stream();
+        let mut req = self
+            .client
+            .post(matrix.url.join("/_matrix/media/r0/upload")?)
+            .bearer_auth(matrix.token.clone())
+            .query(&[("filename", filename)])
+            .header(CONTENT_TYPE, mime.to_string())
+            .body(Body::wrap_stream(content.stream));
+        if let Some(length) = content.length {
+            req = req.header(reqwest::header::CONTENT_LENGTH, length);
+        }
+        let res = req.send().await?;
+        if res.status().is_success() {
+            let upload: Upload = res.json().await?;
+            sql::add_media(cid, &upload.content_uri, &self.db_pool).await?;
+            Ok((upload.content_uri, size))
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+}
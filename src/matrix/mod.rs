@@ -1,8 +1,36 @@
+use reqwest::{StatusCode, Url};
 use serde::{Deserialize, Serialize};
 
+use tokio::sync::OnceCell;
+
 use crate::{error::ServiceError, JMServiceInner};
 
-#[derive(Serialize)]
+pub mod appservice;
+mod bot;
+mod ingest;
+mod media;
+pub mod profile;
+mod sql;
+
+pub use ingest::{MediaContent, MATRIX_IP};
+
+/// Connection to the homeserver and the room memes are mirrored into.
+pub struct Matrix {
+    pub url: Url,
+    pub token: String,
+    pub hs_token: Option<String>,
+    pub domain: String,
+    pub as_id: String,
+    pub sender: String,
+    pub room: String,
+    pub meme_event: String,
+    pub index_event: String,
+    pub user_prefix: String,
+    pub category: Option<String>,
+    pub room_id: OnceCell<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Meme {
     pub category: String,
     pub filename: String,
@@ -24,6 +52,23 @@ pub struct EventID {
     pub event_id: String,
 }
 
+#[derive(Deserialize)]
+pub struct RoomEvent {
+    pub event_id: String,
+    pub sender: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub content: serde_json::Value,
+    pub state_key: Option<String>,
+    pub origin_server_ts: i64,
+}
+
+#[derive(Deserialize)]
+struct Displayname {
+    displayname: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct RegisterRequest {
     #[serde(rename = "type")]
@@ -32,6 +77,14 @@ pub struct RegisterRequest {
 }
 
 impl JMServiceInner {
+    pub fn matrix(&self) -> Result<&Matrix, ServiceError> {
+        self.matrix.as_ref().ok_or(ServiceError::MatrixDisabled)
+    }
+
+    pub fn matrix_enabled(&self) -> bool {
+        self.matrix.is_some()
+    }
+
     pub async fn add_meme(
         &self,
         category: String,
@@ -39,56 +92,98 @@ impl JMServiceInner {
         cid: String,
         user: String,
         id: i64,
+        repost: bool,
     ) -> Result<(), ServiceError> {
+        let message = match repost {
+            true => Some(self.media_message(&user, &filename, &cid).await?),
+            false => None,
+        };
         let meme = Meme {
             category,
             filename,
             cid,
         };
+        let matrix = self.matrix()?;
         let txid = meme.calc_txid(user.clone());
         let usr = self.check_user(user).await?;
         let room_id = self.join_room(&usr).await?;
         let path = format!(
-            "/_matrix/client/r0/rooms/{}/send/es.jensmem.meme/{}",
-            &room_id, txid
+            "/_matrix/client/r0/rooms/{}/send/{}/{}",
+            &room_id, matrix.meme_event, txid
         );
-        let url = self.matrix_url.join(path.as_str())?;
+        let url = matrix.url.join(path.as_str())?;
         let req = self
             .client
             .put(url)
-            .bearer_auth(self.matrix_token.clone())
+            .bearer_auth(matrix.token.clone())
             .query(&usr)
             .json(&meme);
         let res = req.send().await?;
+        if !res.status().is_success() {
+            return Err(ServiceError::InvalidResponse(res.status()));
+        }
+        let event: EventID = res.json().await?;
+        self.set_index(&room_id, id, &event).await?;
+
+        // Clients don't render the custom event, so the meme is posted again
+        // as a regular message, unless it was posted in the room. A retried
+        // outbox entry reuses both transaction IDs and therefore doesn't post
+        // duplicates.
+        let message = match message {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let path = format!(
+            "/_matrix/client/r0/rooms/{}/send/m.room.message/{}.media",
+            &room_id, txid
+        );
+        let req = self
+            .client
+            .put(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .query(&usr)
+            .json(&message);
+        let res = req.send().await?;
         if res.status().is_success() {
-            let event: EventID = res.json().await?;
-            let path = format!(
-                "/_matrix/client/r0/rooms/{}/state/es.jensmem.index/{}",
-                &room_id, id
-            );
-            let req = self
-                .client
-                .put(self.matrix_url.join(path.as_str())?)
-                .bearer_auth(self.matrix_token.clone())
-                .json(&event);
-            let res = req.send().await?;
-            if res.status().is_success() {
-                Ok(())
-            } else {
-                Err(ServiceError::InvalidResponse(res.status()))
-            }
+            Ok(())
         } else {
             Err(ServiceError::InvalidResponse(res.status()))
         }
     }
 
-    async fn check_user(&self, user: String) -> Result<UserID, ServiceError> {
-        let username = format!("jm_{}", user);
-        let user = self.get_mxid(username.clone());
+    /// Maps the meme to its event in the index state of the room.
+    pub async fn set_index(
+        &self,
+        room_id: &str,
+        id: i64,
+        event: &EventID,
+    ) -> Result<(), ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/rooms/{}/state/{}/{}",
+            room_id, matrix.index_event, id
+        );
         let req = self
             .client
-            .get(self.matrix_url.join("/_matrix/client/r0/account/whoami")?)
-            .bearer_auth(self.matrix_token.clone())
+            .put(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .json(event);
+        let res = req.send().await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    pub(super) async fn check_user(&self, user: String) -> Result<UserID, ServiceError> {
+        let matrix = self.matrix()?;
+        let username = format!("{}{}", matrix.user_prefix, user);
+        let user = matrix.get_mxid(username.clone());
+        let req = self
+            .client
+            .get(matrix.url.join("/_matrix/client/r0/account/whoami")?)
+            .bearer_auth(matrix.token.clone())
             .query(&user);
         let res = req.send().await?;
         if res.status().is_success() {
@@ -101,10 +196,11 @@ impl JMServiceInner {
     }
 
     async fn register_user(&self, username: String) -> Result<UserID, ServiceError> {
+        let matrix = self.matrix()?;
         let req = self
             .client
-            .post(self.matrix_url.join("/_matrix/client/r0/register")?)
-            .bearer_auth(self.matrix_token.clone())
+            .post(matrix.url.join("/_matrix/client/r0/register")?)
+            .bearer_auth(matrix.token.clone())
             .json(&RegisterRequest::new(username));
         let res = req.send().await?;
         if res.status().is_success() {
@@ -116,13 +212,15 @@ impl JMServiceInner {
     }
 
     async fn join_room(&self, user: &UserID) -> Result<String, ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/join/{}",
+            urlencoding::encode(&matrix.room)
+        );
         let req = self
             .client
-            .post(
-                self.matrix_url
-                    .join("/_matrix/client/r0/join/%23memes%3Atilera.org")?,
-            )
-            .bearer_auth(self.matrix_token.clone())
+            .post(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
             .query(user);
 
         let res = req.send().await?;
@@ -134,9 +232,131 @@ impl JMServiceInner {
         }
     }
 
+    /// Resolves the ID of the meme room once.
+    pub async fn room_id(&self) -> Result<String, ServiceError> {
+        let matrix = self.matrix()?;
+        let room_id = matrix
+            .room_id
+            .get_or_try_init(|| self.resolve_room(matrix))
+            .await?;
+        Ok(room_id.clone())
+    }
+
+    async fn resolve_room(&self, matrix: &Matrix) -> Result<String, ServiceError> {
+        let path = format!(
+            "/_matrix/client/r0/directory/room/{}",
+            urlencoding::encode(&matrix.room)
+        );
+        let req = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone());
+        let res = req.send().await?;
+        if res.status().is_success() {
+            let room: RoomID = res.json().await?;
+            Ok(room.room_id)
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    /// Returns the event the index state of the room maps the meme to, if
+    /// there is one.
+    pub async fn get_index(&self, room_id: &str, id: i32) -> Result<Option<EventID>, ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/rooms/{}/state/{}/{}",
+            room_id, matrix.index_event, id
+        );
+        let req = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone());
+        let res = req.send().await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(res.json().await?)),
+            status => Err(ServiceError::InvalidResponse(status)),
+        }
+    }
+
+    /// Returns the current state events of the room.
+    pub async fn get_room_state(&self, room_id: &str) -> Result<Vec<RoomEvent>, ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!("/_matrix/client/r0/rooms/{}/state", room_id);
+        let req = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone());
+        let res = req.send().await?;
+        if res.status().is_success() {
+            Ok(res.json().await?)
+        } else {
+            Err(ServiceError::InvalidResponse(res.status()))
+        }
+    }
+
+    pub async fn get_event(
+        &self,
+        room_id: &str,
+        event_id: &str,
+    ) -> Result<Option<RoomEvent>, ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/rooms/{}/event/{}",
+            room_id,
+            urlencoding::encode(event_id)
+        );
+        let req = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone());
+        let res = req.send().await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(res.json().await?)),
+            status => Err(ServiceError::InvalidResponse(status)),
+        }
+    }
+
+    /// Returns the display name of a Matrix user, if it has one.
+    pub async fn get_displayname(&self, user_id: &str) -> Result<Option<String>, ServiceError> {
+        let matrix = self.matrix()?;
+        let path = format!(
+            "/_matrix/client/r0/profile/{}/displayname",
+            urlencoding::encode(user_id)
+        );
+        let req = self
+            .client
+            .get(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone());
+        let res = req.send().await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(res.json::<Displayname>().await?.displayname),
+            status => Err(ServiceError::InvalidResponse(status)),
+        }
+    }
+}
+
+impl Matrix {
+    /// Returns the JensMemes user ID of a puppet.
+    pub fn puppet_user(&self, user_id: &str) -> Option<String> {
+        user_id
+            .strip_prefix('@')?
+            .strip_suffix(format!(":{}", self.domain).as_str())?
+            .strip_prefix(&self.user_prefix)
+            .map(str::to_string)
+    }
+
+    /// Matrix ID of the sender of the application service.
+    pub fn bot_id(&self) -> UserID {
+        self.get_mxid(self.sender.clone())
+    }
+
     fn get_mxid(&self, username: String) -> UserID {
         UserID {
-            user_id: format!("@{}:{}", username, self.matrix_domain.clone()),
+            user_id: format!("@{}:{}", username, self.domain.clone()),
         }
     }
 }
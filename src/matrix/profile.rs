@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use tokio::time::timeout;
+
+use crate::{error::ServiceError, media::image_type, JMService, JMServiceInner};
+
+use super::sql::{self, Profile};
+
+/// Profiles changed directly in the database are picked up after this long.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Serialize)]
+struct Displayname<'a> {
+    displayname: &'a str,
+}
+
+#[derive(Serialize)]
+struct AvatarUrl<'a> {
+    avatar_url: &'a str,
+}
+
+/// Keeps the display names and avatars of the puppets in sync with the
+/// profiles of the users that have memes.
+pub async fn run(service: JMService) {
+    if !service.matrix_enabled() {
+        return;
+    }
+    loop {
+        match sql::get_changed_profiles(&service.db_pool).await {
+            Ok(profiles) => {
+                for profile in &profiles {
+                    if let Err(err) = service.sync_profile(profile).await {
+                        eprintln!("Matrix profile sync error for {}: {}", profile.userid, err);
+                    }
+                }
+            },
+            Err(err) => eprintln!("Matrix profile sync error: {}", err),
+        }
+        let _ = timeout(SYNC_INTERVAL, service.profile_notify.notified()).await;
+    }
+}
+
+impl JMServiceInner {
+    async fn sync_profile(&self, profile: &Profile) -> Result<(), ServiceError> {
+        let matrix = self.matrix()?;
+        let usr = self.check_user(profile.userid.clone()).await?;
+        let path = format!(
+            "/_matrix/client/r0/profile/{}/displayname",
+            urlencoding::encode(&usr.user_id)
+        );
+        let res = self
+            .client
+            .put(matrix.url.join(path.as_str())?)
+            .bearer_auth(matrix.token.clone())
+            .query(&usr)
+            .json(&Displayname {
+                displayname: &profile.name,
+            })
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(ServiceError::InvalidResponse(res.status()));
+        }
+
+        if let Some(cid) = &profile.avatar {
+            let mxc = self.upload_avatar(cid).await?;
+            let path = format!(
+                "/_matrix/client/r0/profile/{}/avatar_url",
+                urlencoding::encode(&usr.user_id)
+            );
+            let res = self
+                .client
+                .put(matrix.url.join(path.as_str())?)
+                .bearer_auth(matrix.token.clone())
+                .query(&usr)
+                .json(&AvatarUrl { avatar_url: &mxc })
+                .send()
+                .await?;
+            if !res.status().is_success() {
+                return Err(ServiceError::InvalidResponse(res.status()));
+            }
+        }
+        sql::set_profile(profile, &self.db_pool).await?;
+        Ok(())
+    }
+
+    /// Uploads an avatar with a file name matching its image type, so that
+    /// the homeserver can create thumbnails of it.
+    async fn upload_avatar(&self, cid: &str) -> Result<String, ServiceError> {
+        let mut content = self.storage.cat_range(cid, 0, 16).await?;
+        let mut head = vec![];
+        while let Some(chunk) = content.stream.try_next().await? {
+            head.extend_from_slice(&chunk);
+        }
+        let extension = image_type(&head)
+            .and_then(|mime| mime.strip_prefix("image/"))
+            .unwrap_or("bin");
+        let (mxc, _) = self
+            .upload_media(cid, &format!("avatar.{}", extension))
+            .await?;
+        Ok(mxc)
+    }
+}
@@ -0,0 +1,48 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+/// Returns the content URI a file was uploaded to the homeserver with.
+pub async fn get_media(cid: &str, pool: &PgPool) -> Result<Option<String>> {
+    sqlx::query("SELECT mxc FROM matrix_media WHERE cid = $1")
+        .bind(cid)
+        .map(|row: PgRow| row.get("mxc"))
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn add_media(cid: &str, mxc: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO matrix_media (cid, mxc) VALUES ($1, $2) ON CONFLICT (cid) DO UPDATE SET mxc = EXCLUDED.mxc")
+        .bind(cid)
+        .bind(mxc)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub struct Profile {
+    pub userid: String,
+    pub name: String,
+    pub avatar: Option<String>,
+}
+
+/// Returns the profiles of users with memes that differ from the ones last
+/// set on their puppets.
+pub async fn get_changed_profiles(pool: &PgPool) -> Result<Vec<Profile>> {
+    sqlx::query("SELECT users.id, COALESCE(users.name, users.id) AS name, users.avatar FROM users LEFT JOIN matrix_profiles ON users.id = matrix_profiles.userid WHERE EXISTS (SELECT 1 FROM memes WHERE memes.userid = users.id) AND (matrix_profiles.userid IS NULL OR COALESCE(users.name, users.id) IS DISTINCT FROM matrix_profiles.name OR users.avatar IS DISTINCT FROM matrix_profiles.avatar)")
+        .map(|row: PgRow| Profile {
+            userid: row.get("id"),
+            name: row.get("name"),
+            avatar: row.get("avatar"),
+        })
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn set_profile(profile: &Profile, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO matrix_profiles (userid, name, avatar) VALUES ($1, $2, $3) ON CONFLICT (userid) DO UPDATE SET name = EXCLUDED.name, avatar = EXCLUDED.avatar")
+        .bind(&profile.userid)
+        .bind(&profile.name)
+        .bind(&profile.avatar)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use std::io::Cursor;
+
+use futures_util::TryStreamExt;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+
+use crate::{error::ServiceError, JMServiceInner};
+
+/// Number of bytes fetched to find the dimensions of an image. Every format
+/// stores them in its header, only JPEG files with large metadata segments
+/// might need more.
+const PROBE_SIZE: u64 = 64 * 1024;
+/// Longest side of the thumbnails in the gallery grid.
+const THUMBNAIL_SIZE: u32 = 480;
+
+/// Returns the MIME type of a PNG, GIF, JPEG or WebP image.
+pub fn image_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if data.starts_with(b"\xff\xd8") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Scales an image down to fit into a square of `size` pixels. Images with
+/// transparency become PNGs, others JPEGs. Returns `None` for images that
+/// can't be decoded or are small enough already.
+pub fn thumbnail(data: &[u8], size: u32) -> Option<(Vec<u8>, &'static str)> {
+    let image = image::load_from_memory(data).ok()?;
+    let (width, height) = image.dimensions();
+    if width <= size && height <= size {
+        return None;
+    }
+    let thumbnail = image.thumbnail(size, size);
+    let mut data = Cursor::new(vec![]);
+    let filename = if thumbnail.color().has_alpha() {
+        thumbnail.write_to(&mut data, ImageOutputFormat::Png).ok()?;
+        "thumbnail.png"
+    } else {
+        DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+            .write_to(&mut data, ImageOutputFormat::Jpeg(85))
+            .ok()?;
+        "thumbnail.jpg"
+    };
+    Some((data.into_inner(), filename))
+}
+
+/// Reads the width and height from the header of a PNG, GIF, JPEG or WebP
+/// image.
+pub fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some((be32(data, 16)?, be32(data, 20)?))
+    } else if data.starts_with(b"GIF8") {
+        Some((le16(data, 6)? as u32, le16(data, 8)? as u32))
+    } else if data.starts_with(b"\xff\xd8") {
+        jpeg_dimensions(data)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        webp_dimensions(data)
+    } else {
+        None
+    }
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let mut pos = 2;
+    loop {
+        while *data.get(pos)? != 0xff {
+            pos += 1;
+        }
+        while *data.get(pos)? == 0xff {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+        match marker {
+            0xd8 | 0x01 | 0xd0..=0xd7 => continue,
+            // Start of scan or end of image, the frame header is always before
+            0xda | 0xd9 => return None,
+            // Start of frame markers, except DHT, JPG and DAC
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                return Some((be16(data, pos + 5)? as u32, be16(data, pos + 3)? as u32));
+            },
+            _ => pos += be16(data, pos)? as usize,
+        }
+    }
+}
+
+fn webp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    match data.get(12..16)? {
+        b"VP8 " => Some((
+            (le16(data, 26)? & 0x3fff) as u32,
+            (le16(data, 28)? & 0x3fff) as u32,
+        )),
+        b"VP8L" => {
+            let bits = u32::from_le_bytes([
+                *data.get(21)?,
+                *data.get(22)?,
+                *data.get(23)?,
+                *data.get(24)?,
+            ]);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        },
+        b"VP8X" => Some((le24(data, 24)? + 1, le24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+fn be16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn be32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes([
+        *data.get(pos)?,
+        *data.get(pos + 1)?,
+        *data.get(pos + 2)?,
+        *data.get(pos + 3)?,
+    ]))
+}
+
+fn le16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn le24(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes([
+        *data.get(pos)?,
+        *data.get(pos + 1)?,
+        *data.get(pos + 2)?,
+        0,
+    ]))
+}
+
+impl JMServiceInner {
+    /// Fetches the beginning of an image from the storage to read its
+    /// dimensions.
+    pub async fn probe_dimensions(&self, cid: String) -> Result<Option<(u32, u32)>, ServiceError> {
+        let mut content = self.storage.cat_range(&cid, 0, PROBE_SIZE).await?;
+        let mut data = vec![];
+        while let Some(chunk) = content.stream.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(image_dimensions(&data))
+    }
+
+    /// Returns the CID and filename of a scaled-down version of an image,
+    /// which is created and pinned on first use. Files that can't be scaled
+    /// down are their own thumbnail.
+    pub async fn thumbnail(
+        &self,
+        cid: &str,
+        filename: &str,
+    ) -> Result<(String, String), ServiceError> {
+        if let Some(thumbnail) = self.get_thumbnail(cid).await? {
+            return Ok(thumbnail);
+        }
+        let mime = new_mime_guess::from_path(filename).first_or_octet_stream();
+        if mime.type_() != "image" {
+            return Ok((cid.to_string(), filename.to_string()));
+        }
+        let mut content = self.storage.cat(cid).await?;
+        let mut data = vec![];
+        while let Some(chunk) = content.stream.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        let scaled = tokio::task::spawn_blocking(move || thumbnail(&data, THUMBNAIL_SIZE))
+            .await
+            .map_err(std::io::Error::other)?;
+        let (thumbnail, name) = match scaled {
+            Some((data, name)) => {
+                let stored = self.storage.add(data.into(), name.to_string()).await?;
+                self.pin(&stored.cid).await?;
+                (stored.cid, name.to_string())
+            },
+            None => (cid.to_string(), filename.to_string()),
+        };
+        self.add_thumbnail(cid, &thumbnail, &name).await?;
+        Ok((thumbnail, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(format: ImageOutputFormat, width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(vec![]);
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut data, format)
+            .unwrap();
+        data.into_inner()
+    }
+
+    fn webp(chunk: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+        data.extend_from_slice(chunk);
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn dimensions_of_encoded_images() {
+        for format in [
+            ImageOutputFormat::Png,
+            ImageOutputFormat::Gif,
+            ImageOutputFormat::Jpeg(80),
+        ] {
+            assert_eq!(
+                image_dimensions(&encode(format, 300, 200)),
+                Some((300, 200))
+            );
+        }
+    }
+
+    #[test]
+    fn dimensions_of_webp_images() {
+        let lossy = webp(
+            b"VP8 ",
+            &[0, 0, 0, 0x9d, 0x01, 0x2a, 0x2c, 0x01, 0xc8, 0x00],
+        );
+        assert_eq!(image_dimensions(&lossy), Some((300, 200)));
+        let bits: u32 = 299 | (199 << 14);
+        let mut lossless = vec![0x2f];
+        lossless.extend_from_slice(&bits.to_le_bytes());
+        assert_eq!(
+            image_dimensions(&webp(b"VP8L", &lossless)),
+            Some((300, 200))
+        );
+        let extended = webp(b"VP8X", &[0, 0, 0, 0, 0x2b, 0x01, 0, 0xc7, 0, 0]);
+        assert_eq!(image_dimensions(&extended), Some((300, 200)));
+    }
+
+    #[test]
+    fn types_of_images() {
+        assert_eq!(
+            image_type(&encode(ImageOutputFormat::Png, 1, 1)),
+            Some("image/png")
+        );
+        assert_eq!(
+            image_type(&encode(ImageOutputFormat::Gif, 1, 1)),
+            Some("image/gif")
+        );
+        assert_eq!(
+            image_type(&encode(ImageOutputFormat::Jpeg(80), 1, 1)),
+            Some("image/jpeg")
+        );
+        assert_eq!(image_type(&webp(b"VP8L", &[0x2f])), Some("image/webp"));
+        assert_eq!(image_type(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(image_type(b"<svg></svg>"), None);
+    }
+
+    #[test]
+    fn dimensions_of_truncated_or_unknown_data() {
+        let png = encode(ImageOutputFormat::Png, 300, 200);
+        assert_eq!(image_dimensions(&png[..20]), None);
+        assert_eq!(image_dimensions(b"not an image"), None);
+        assert_eq!(image_dimensions(b"\xff\xd8\xff\xd9"), None);
+    }
+}
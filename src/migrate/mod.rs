@@ -0,0 +1,219 @@
+use std::path::PathBuf;
+
+use axum::body::Bytes;
+use reqwest::{StatusCode, Url};
+use sqlx::MySqlPool;
+
+use crate::{error::ServiceError, JMServiceInner};
+
+pub use self::sql::reset_meme_sequence;
+use self::sql::LegacyMeme;
+
+mod sql;
+
+/// Where the files of the legacy server are read from. It stored them as
+/// `<user>/<filename>` and served them from its CDN.
+pub enum LegacyFiles {
+    Dir(PathBuf),
+    Cdn(Url),
+}
+
+#[derive(Default)]
+pub struct TableReport {
+    pub total: usize,
+    pub new: usize,
+}
+
+#[derive(Default)]
+pub struct MigrationReport {
+    pub categories: TableReport,
+    pub users: TableReport,
+    pub tokens: TableReport,
+    pub memes: TableReport,
+    pub missing_files: Vec<String>,
+    /// Memes without a category, skipped as no default category was given
+    pub uncategorized: Vec<String>,
+    /// Legacy memes whose ID is taken by a different meme
+    pub collisions: Vec<String>,
+}
+
+impl JMServiceInner {
+    /// Copies categories, users, tokens and memes of the legacy MariaDB
+    /// database with their IDs and timestamps. Rows that already exist are
+    /// skipped, so an interrupted migration can be run again, and memes are
+    /// reported as collisions if their ID is taken by a different file. In a
+    /// dry run nothing is written, but the report still lists what would be
+    /// migrated; only the names of existing memes are compared then, as the
+    /// CIDs of the legacy files are not known without adding them.
+    pub async fn migrate(
+        &self,
+        database: &str,
+        files: &LegacyFiles,
+        default_category: Option<&str>,
+        dry_run: bool,
+        matrix: bool,
+    ) -> Result<MigrationReport, ServiceError> {
+        if matrix {
+            self.matrix()?;
+        }
+        let legacy = MySqlPool::new(database).await?;
+        let mut report = MigrationReport::default();
+
+        let categories = sql::get_legacy_categories(&legacy).await?;
+        if let Some(category) = default_category {
+            if !categories.iter().any(|legacy| legacy.id == category)
+                && self.get_category(&category.to_string()).await?.is_none()
+            {
+                return Err(ServiceError::NotFound(format!("Category {}", category)));
+            }
+        }
+        for category in categories {
+            let new = if dry_run {
+                !sql::exists("categories", "id", &category.id, &self.db_pool).await?
+            } else {
+                sql::add_category(&category, &self.db_pool).await?
+            };
+            report.categories.count(new);
+        }
+        for user in sql::get_legacy_users(&legacy).await? {
+            let new = if dry_run {
+                !sql::exists("users", "id", &user.id, &self.db_pool).await?
+            } else {
+                sql::add_user(&user, &self.db_pool).await?
+            };
+            report.users.count(new);
+        }
+        for token in sql::get_legacy_tokens(&legacy).await? {
+            let new = if dry_run {
+                !sql::exists("token", "uid", &token.uid, &self.db_pool).await?
+            } else {
+                sql::add_token(&token, &self.db_pool).await?
+            };
+            report.tokens.count(new);
+        }
+
+        let actions = sql::meme_actions(matrix);
+        for meme in sql::get_legacy_memes(&legacy).await? {
+            let path = format!("{}/{}", meme.user, meme.filename);
+            if let Some(stored) = sql::get_meme(meme.id, &self.db_pool).await? {
+                if stored.filename != meme.filename || stored.userid != meme.user {
+                    report.collisions.push(format!(
+                        "{} is {}/{}, not {}",
+                        meme.id, stored.userid, stored.filename, path
+                    ));
+                } else if !dry_run {
+                    if let Some(file) = self.read_legacy_file(files, &meme).await? {
+                        let added = self.storage.add(file, meme.filename.clone()).await?;
+                        if added.cid != stored.cid {
+                            report.collisions.push(format!(
+                                "{} of {} has CID {}, not {}",
+                                meme.id, path, stored.cid, added.cid
+                            ));
+                        }
+                    }
+                }
+                report.memes.count(false);
+                continue;
+            }
+            let category = match meme.category.as_deref().or(default_category) {
+                Some(category) => category.to_string(),
+                None => {
+                    report.uncategorized.push(path);
+                    continue;
+                },
+            };
+            let file = if dry_run {
+                self.legacy_file_exists(files, &meme)
+                    .await?
+                    .then(Bytes::new)
+            } else {
+                self.read_legacy_file(files, &meme).await?
+            };
+            let file = match file {
+                Some(file) => file,
+                None => {
+                    report.missing_files.push(path);
+                    continue;
+                },
+            };
+            if !dry_run {
+                let stored = self.storage.add(file, meme.filename.clone()).await?;
+                sql::add_meme(
+                    &meme,
+                    &category,
+                    &stored.cid,
+                    stored.size,
+                    &actions,
+                    &self.db_pool,
+                )
+                .await?;
+            }
+            report.memes.count(true);
+        }
+
+        if !dry_run {
+            sql::reset_meme_sequence(&self.db_pool).await?;
+            self.outbox_notify.notify_one();
+        }
+        Ok(report)
+    }
+
+    async fn read_legacy_file(
+        &self,
+        files: &LegacyFiles,
+        meme: &LegacyMeme,
+    ) -> Result<Option<Bytes>, ServiceError> {
+        match files {
+            LegacyFiles::Dir(dir) => {
+                match tokio::fs::read(dir.join(&meme.user).join(&meme.filename)).await {
+                    Ok(data) => Ok(Some(Bytes::from(data))),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(err.into()),
+                }
+            },
+            LegacyFiles::Cdn(cdn) => {
+                let res = self.client.get(legacy_url(cdn, meme)?).send().await?;
+                match res.status() {
+                    StatusCode::NOT_FOUND => Ok(None),
+                    status if status.is_success() => Ok(Some(res.bytes().await?)),
+                    status => Err(ServiceError::InvalidResponse(status)),
+                }
+            },
+        }
+    }
+
+    async fn legacy_file_exists(
+        &self,
+        files: &LegacyFiles,
+        meme: &LegacyMeme,
+    ) -> Result<bool, ServiceError> {
+        match files {
+            LegacyFiles::Dir(dir) => Ok(dir.join(&meme.user).join(&meme.filename).is_file()),
+            LegacyFiles::Cdn(cdn) => {
+                let res = self.client.head(legacy_url(cdn, meme)?).send().await?;
+                match res.status() {
+                    StatusCode::NOT_FOUND => Ok(false),
+                    status if status.is_success() => Ok(true),
+                    status => Err(ServiceError::InvalidResponse(status)),
+                }
+            },
+        }
+    }
+}
+
+impl TableReport {
+    fn count(&mut self, new: bool) {
+        self.total += 1;
+        if new {
+            self.new += 1;
+        }
+    }
+}
+
+fn legacy_url(cdn: &Url, meme: &LegacyMeme) -> Result<Url, ServiceError> {
+    Ok(cdn.join(&format!(
+        "{}/{}",
+        urlencoding::encode(&meme.user),
+        urlencoding::encode(&meme.filename)
+    ))?)
+}
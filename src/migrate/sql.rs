@@ -0,0 +1,197 @@
+use sqlx::{mysql::MySqlRow, postgres::PgRow, MySqlPool, PgPool, Result, Row};
+
+use crate::outbox;
+
+pub struct LegacyCategory {
+    pub num: i64,
+    pub id: String,
+    pub name: Option<String>,
+}
+
+pub struct LegacyUser {
+    pub id: String,
+    pub name: Option<String>,
+    pub authsource: Option<String>,
+}
+
+pub struct LegacyToken {
+    pub uid: String,
+    pub token: Option<String>,
+}
+
+pub struct LegacyMeme {
+    pub id: i64,
+    pub filename: String,
+    pub user: String,
+    pub category: Option<String>,
+    pub timestamp: Option<i64>,
+    pub ip: Option<String>,
+}
+
+pub async fn get_legacy_categories(pool: &MySqlPool) -> Result<Vec<LegacyCategory>> {
+    let q: Vec<LegacyCategory> =
+        sqlx::query("SELECT CAST(num AS SIGNED) AS num, id, name FROM categories ORDER BY num")
+            .map(|row: MySqlRow| LegacyCategory {
+                num: row.get("num"),
+                id: row.get("id"),
+                name: row.get("name"),
+            })
+            .fetch_all(pool)
+            .await?;
+    Ok(q)
+}
+
+pub async fn get_legacy_users(pool: &MySqlPool) -> Result<Vec<LegacyUser>> {
+    let q: Vec<LegacyUser> = sqlx::query(
+        "SELECT id, name, CAST(authsource AS CHAR) AS authsource FROM users ORDER BY id",
+    )
+    .map(|row: MySqlRow| LegacyUser {
+        id: row.get("id"),
+        name: row.get("name"),
+        authsource: row.get("authsource"),
+    })
+    .fetch_all(pool)
+    .await?;
+    Ok(q)
+}
+
+pub async fn get_legacy_tokens(pool: &MySqlPool) -> Result<Vec<LegacyToken>> {
+    let q: Vec<LegacyToken> = sqlx::query("SELECT uid, token FROM token ORDER BY uid")
+        .map(|row: MySqlRow| LegacyToken {
+            uid: row.get("uid"),
+            token: row.get("token"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn get_legacy_memes(pool: &MySqlPool) -> Result<Vec<LegacyMeme>> {
+    let q: Vec<LegacyMeme> = sqlx::query("SELECT CAST(id AS SIGNED) AS id, filename, user, category, CAST(UNIX_TIMESTAMP(timestamp) AS SIGNED) AS ts, ip FROM memes ORDER BY id")
+        .map(|row: MySqlRow| LegacyMeme {
+            id: row.get("id"),
+            filename: row.get("filename"),
+            user: row.get("user"),
+            category: row.get("category"),
+            timestamp: row.get("ts"),
+            ip: row.get("ip"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+/// A meme already in the Postgres database under the ID of a legacy meme.
+pub struct StoredMeme {
+    pub filename: String,
+    pub userid: String,
+    pub cid: String,
+}
+
+pub async fn get_meme(id: i64, pool: &PgPool) -> Result<Option<StoredMeme>> {
+    let q: Option<StoredMeme> =
+        sqlx::query("SELECT filename, userid, cid FROM memes WHERE id = $1")
+            .bind(id as i32)
+            .map(|row: PgRow| StoredMeme {
+                filename: row.get("filename"),
+                userid: row.get("userid"),
+                cid: row.get("cid"),
+            })
+            .fetch_optional(pool)
+            .await?;
+    Ok(q)
+}
+
+/// Returns whether a row with the key exists in the Postgres table.
+pub async fn exists(table: &str, column: &str, key: &str, pool: &PgPool) -> Result<bool> {
+    let q: Option<i32> = sqlx::query(&format!(
+        "SELECT 1 AS found FROM {} WHERE {}::text = $1",
+        table, column
+    ))
+    .bind(key)
+    .map(|row: PgRow| row.get("found"))
+    .fetch_optional(pool)
+    .await?;
+    Ok(q.is_some())
+}
+
+pub async fn add_category(category: &LegacyCategory, pool: &PgPool) -> Result<bool> {
+    let q = sqlx::query(
+        "INSERT INTO categories (num, id, name) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+    )
+    .bind(category.num as i32)
+    .bind(&category.id)
+    .bind(&category.name)
+    .execute(pool)
+    .await?;
+    Ok(q > 0)
+}
+
+pub async fn add_user(user: &LegacyUser, pool: &PgPool) -> Result<bool> {
+    let q = sqlx::query(
+        "INSERT INTO users (id, name, authsource) VALUES ($1, $2, $3::json) ON CONFLICT DO NOTHING",
+    )
+    .bind(&user.id)
+    .bind(&user.name)
+    .bind(&user.authsource)
+    .execute(pool)
+    .await?;
+    Ok(q > 0)
+}
+
+pub async fn add_token(token: &LegacyToken, pool: &PgPool) -> Result<bool> {
+    let q = sqlx::query("INSERT INTO token (uid, token) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(&token.uid)
+        .bind(&token.token)
+        .execute(pool)
+        .await?;
+    Ok(q > 0)
+}
+
+/// Inserts the meme with its original ID and queues the outbox actions.
+pub async fn add_meme(
+    meme: &LegacyMeme,
+    category: &str,
+    cid: &str,
+    size: i64,
+    actions: &[&str],
+    pool: &PgPool,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO memes (id, filename, userid, category, timestamp, ip, cid, size) VALUES ($1, $2, $3, $4, TO_TIMESTAMP($5) AT TIME ZONE 'UTC', $6, $7, $8)")
+        .bind(meme.id as i32)
+        .bind(&meme.filename)
+        .bind(&meme.user)
+        .bind(category)
+        .bind(meme.timestamp.map(|timestamp| timestamp as f64))
+        .bind(&meme.ip)
+        .bind(cid)
+        .bind(size)
+        .execute(&mut tx)
+        .await?;
+    for action in actions {
+        sqlx::query("INSERT INTO outbox (memeid, action) VALUES ($1, $2)")
+            .bind(meme.id as i32)
+            .bind(*action)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Moves the ID sequence of the memes past the migrated IDs.
+pub async fn reset_meme_sequence(pool: &PgPool) -> Result<()> {
+    sqlx::query("SELECT setval(pg_get_serial_sequence('memes', 'id'), GREATEST((SELECT MAX(id) FROM memes), 1))")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub fn meme_actions(matrix: bool) -> Vec<&'static str> {
+    if matrix {
+        vec![outbox::ACTION_PIN, outbox::ACTION_MATRIX]
+    } else {
+        vec![outbox::ACTION_PIN]
+    }
+}
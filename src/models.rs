@@ -31,10 +31,43 @@ pub struct Count {
     pub count: i64,
 }
 
+#[derive(Serialize)]
+pub struct OutboxEntry {
+    pub id: i32,
+    pub meme: i32,
+    pub action: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created: i32,
+    pub next_attempt: i32,
+    pub done: Option<i32>,
+    pub failed: Option<i32>,
+}
+
+#[derive(Serialize)]
+pub struct ConsistencyCheck {
+    pub id: i32,
+    pub started: i32,
+    pub finished: Option<i32>,
+    pub last_meme: i32,
+    pub checked: i32,
+    pub issues: Vec<ConsistencyIssue>,
+}
+
+#[derive(Serialize)]
+pub struct ConsistencyIssue {
+    pub meme: i32,
+    pub cid: String,
+    pub issue: String,
+    pub repaired: bool,
+}
+
 pub enum UserIdentifier {
     Id(String),
     Token(String),
     Username(String),
+    /// Matrix ID stored under `matrix` in the auth source of the user
+    Matrix(String),
     Null,
 }
 
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use tokio::time::timeout;
+
+use crate::{error::ServiceError, JMService, JMServiceInner};
+
+use self::sql::DueEntry;
+
+pub mod sql;
+
+pub const ACTION_MATRIX: &str = "matrix";
+/// Only sends the meme event of memes posted in the Matrix room.
+pub const ACTION_MATRIX_EVENT: &str = "matrix_event";
+pub const ACTION_PIN: &str = "pin";
+
+const BATCH_SIZE: i64 = 20;
+const IDLE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_DELAY: i32 = 60 * 60;
+const MAX_ATTEMPTS: i32 = 20;
+/// Time a worker has to finish a claimed entry before it is retried.
+const LEASE: i32 = 10 * 60;
+
+/// Runs the outbox worker forever. It is woken up by `outbox_notify` after
+/// every upload and polls the table in between, so failed entries are retried
+/// once their backoff elapsed, until they are given up after `MAX_ATTEMPTS`.
+pub async fn run(service: JMService) {
+    loop {
+        match service.process_outbox().await {
+            Ok(count) if count > 0 => continue,
+            Ok(_) => (),
+            Err(err) => eprintln!("Outbox error: {}", err),
+        }
+        let _ = timeout(IDLE_INTERVAL, service.outbox_notify.notified()).await;
+    }
+}
+
+impl JMServiceInner {
+    pub async fn process_outbox(&self) -> Result<usize, sqlx::Error> {
+        let entries = sql::get_due(BATCH_SIZE, LEASE, &self.db_pool).await?;
+        let count = entries.len();
+        for entry in entries {
+            match self.run_outbox_entry(&entry).await {
+                Ok(()) => sql::complete(entry.id, &self.db_pool).await?,
+                Err(err) => {
+                    let delay = MAX_DELAY.min(10 << entry.attempts.min(12));
+                    sql::fail(
+                        entry.id,
+                        err.to_string(),
+                        delay,
+                        MAX_ATTEMPTS,
+                        &self.db_pool,
+                    )
+                    .await?
+                },
+            }
+        }
+        Ok(count)
+    }
+
+    async fn run_outbox_entry(&self, entry: &DueEntry) -> Result<(), ServiceError> {
+        match entry.action.as_str() {
+            ACTION_MATRIX | ACTION_MATRIX_EVENT => {
+                self.add_meme(
+                    entry.category.clone(),
+                    entry.filename.clone(),
+                    entry.cid.clone(),
+                    entry.userid.clone(),
+                    entry.meme_id as i64,
+                    entry.action == ACTION_MATRIX,
+                )
+                .await
+            },
+            ACTION_PIN => self.pin(&entry.cid).await,
+            action => Err(ServiceError::UnknownAction(action.to_string())),
+        }
+    }
+}
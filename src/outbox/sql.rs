@@ -0,0 +1,93 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+use crate::models::OutboxEntry;
+
+pub struct DueEntry {
+    pub id: i32,
+    pub action: String,
+    pub attempts: i32,
+    pub meme_id: i32,
+    pub filename: String,
+    pub userid: String,
+    pub category: String,
+    pub cid: String,
+}
+
+/// Claims the due entries by moving their next attempt `lease` seconds into
+/// the future, so that other workers skip them until they are completed or
+/// the worker died.
+pub async fn get_due(limit: i64, lease: i32, pool: &PgPool) -> Result<Vec<DueEntry>> {
+    let q: Vec<DueEntry> = sqlx::query("WITH claimed AS (UPDATE outbox SET next_attempt = NOW() + ($2 * INTERVAL '1 second') WHERE id IN (SELECT id FROM outbox WHERE done IS NULL AND failed IS NULL AND next_attempt <= NOW() ORDER BY id LIMIT $1 FOR UPDATE SKIP LOCKED) RETURNING id, action, attempts, memeid) SELECT claimed.id, action, attempts, memeid, filename, userid, COALESCE(category, '') AS category, cid FROM claimed, memes WHERE claimed.memeid = memes.id ORDER BY claimed.id")
+        .bind(limit)
+        .bind(lease)
+        .map(|row: PgRow| DueEntry {
+            id: row.get("id"),
+            action: row.get("action"),
+            attempts: row.get("attempts"),
+            meme_id: row.get("memeid"),
+            filename: row.get("filename"),
+            userid: row.get("userid"),
+            category: row.get("category"),
+            cid: row.get("cid"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn complete(id: i32, pool: &PgPool) -> Result<()> {
+    sqlx::query(
+        "UPDATE outbox SET done = NOW(), attempts = attempts + 1, error = NULL WHERE id = $1",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Schedules the next attempt, or gives the entry up once it failed
+/// `max_attempts` times.
+pub async fn fail(
+    id: i32,
+    error: String,
+    delay: i32,
+    max_attempts: i32,
+    pool: &PgPool,
+) -> Result<()> {
+    sqlx::query("UPDATE outbox SET attempts = attempts + 1, error = $2, next_attempt = NOW() + ($3 * INTERVAL '1 second'), failed = CASE WHEN attempts + 1 >= $4 THEN NOW() END WHERE id = $1")
+        .bind(id)
+        .bind(error)
+        .bind(delay)
+        .bind(max_attempts)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn retry(id: i32, pool: &PgPool) -> Result<u64> {
+    let q = sqlx::query("UPDATE outbox SET next_attempt = NOW(), attempts = CASE WHEN failed IS NULL THEN attempts ELSE 0 END, failed = NULL WHERE id = $1 AND done IS NULL")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(q)
+}
+
+pub async fn get_entries(pending: bool, limit: i64, pool: &PgPool) -> Result<Vec<OutboxEntry>> {
+    let q: Vec<OutboxEntry> = sqlx::query("SELECT id, memeid, action, attempts, error, UNIX_TIMESTAMP(created) AS created, UNIX_TIMESTAMP(next_attempt) AS next_attempt, UNIX_TIMESTAMP(done) AS done, UNIX_TIMESTAMP(failed) AS failed FROM outbox WHERE (done IS NULL) = $1 ORDER BY id DESC LIMIT $2")
+        .bind(pending)
+        .bind(limit)
+        .map(|row: PgRow| OutboxEntry {
+            id: row.get("id"),
+            meme: row.get("memeid"),
+            action: row.get("action"),
+            attempts: row.get("attempts"),
+            error: row.get("error"),
+            created: row.get("created"),
+            next_attempt: row.get("next_attempt"),
+            done: row.get("done"),
+            failed: row.get("failed"),
+        })
+        .fetch_all(pool)
+        .await?;
+    Ok(q)
+}
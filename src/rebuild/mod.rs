@@ -0,0 +1,202 @@
+use crate::{
+    error::ServiceError,
+    matrix::{EventID, MediaContent, Meme},
+    migrate::reset_meme_sequence,
+    models::UserIdentifier,
+    JMServiceInner,
+};
+
+use self::sql::RebuiltMeme;
+
+mod sql;
+
+#[derive(Default)]
+pub struct RebuildReport {
+    pub memes: usize,
+    pub users: usize,
+    pub categories: usize,
+    pub skipped: Vec<String>,
+}
+
+/// Where the file of a rebuilt meme is.
+enum MemeFile {
+    /// CID of a meme event
+    Stored(String),
+    /// Content URI of an image or video posted in the room
+    Media(String),
+}
+
+impl JMServiceInner {
+    /// Restores the memes of an empty database from the index state of the
+    /// Matrix room, which maps every meme ID to the event the meme was sent
+    /// with. Older servers indexed images and videos posted in the room
+    /// directly, those are downloaded from the homeserver and belong to the
+    /// user linked to the sender. Users and categories the memes reference
+    /// are only created if requested, otherwise the memes of missing ones
+    /// are skipped.
+    pub async fn rebuild(
+        &self,
+        users: bool,
+        categories: bool,
+    ) -> Result<RebuildReport, ServiceError> {
+        let matrix = self.matrix()?;
+        if sql::count_memes(&self.db_pool).await? > 0 {
+            return Err(ServiceError::NotEmpty("memes".to_string()));
+        }
+        let room_id = self.room_id().await?;
+        let mut index: Vec<(i32, String)> = self
+            .get_room_state(&room_id)
+            .await?
+            .into_iter()
+            .filter(|event| event.event_type == matrix.index_event)
+            .filter_map(|event| {
+                let id = event.state_key?.parse().ok()?;
+                let target: EventID = serde_json::from_value(event.content).ok()?;
+                Some((id, target.event_id))
+            })
+            .collect();
+        index.sort_by_key(|(id, _)| *id);
+
+        let mut report = RebuildReport::default();
+        for (id, event_id) in index {
+            let event = match self.get_event(&room_id, &event_id).await? {
+                Some(event) => event,
+                None => {
+                    report
+                        .skipped
+                        .push(format!("meme {}: {} not found", id, event_id));
+                    continue;
+                },
+            };
+            let (user, filename, category, file) = if event.event_type == matrix.meme_event {
+                let meme: Meme = match serde_json::from_value(event.content) {
+                    Ok(meme) => meme,
+                    Err(_) => {
+                        report
+                            .skipped
+                            .push(format!("meme {}: {} was redacted", id, event_id));
+                        continue;
+                    },
+                };
+                let user = match matrix.puppet_user(&event.sender) {
+                    Some(user) => user,
+                    None => {
+                        report
+                            .skipped
+                            .push(format!("meme {}: {} is no puppet", id, event.sender));
+                        continue;
+                    },
+                };
+                (
+                    user,
+                    meme.filename,
+                    meme.category,
+                    MemeFile::Stored(meme.cid),
+                )
+            } else if event.event_type == "m.room.message" {
+                let media = match serde_json::from_value::<MediaContent>(event.content) {
+                    Ok(media) if media.url.is_some() => media,
+                    _ => {
+                        report
+                            .skipped
+                            .push(format!("meme {}: {} is no media message", id, event_id));
+                        continue;
+                    },
+                };
+                let sender = &event.sender;
+                let linked = self
+                    .get_user(UserIdentifier::Matrix(event.sender.clone()))
+                    .await?
+                    .map(|user| user.id);
+                let user = match linked.or_else(|| matrix.puppet_user(sender)) {
+                    Some(user) => user,
+                    None => {
+                        report.skipped.push(format!(
+                            "meme {}: {} is not linked to a user",
+                            id, event.sender
+                        ));
+                        continue;
+                    },
+                };
+                let category = match media.category().or(matrix.category.as_deref()) {
+                    Some(category) => category.to_string(),
+                    None => {
+                        report
+                            .skipped
+                            .push(format!("meme {}: {} has no category", id, event_id));
+                        continue;
+                    },
+                };
+                let filename = media.filename().replace('/', "_");
+                let mxc = media.url.unwrap_or_default();
+                (user, filename, category, MemeFile::Media(mxc))
+            } else {
+                report
+                    .skipped
+                    .push(format!("meme {}: {} is no meme event", id, event_id));
+                continue;
+            };
+
+            let new_user = !sql::has_user(&user, &self.db_pool).await?;
+            let new_category = !sql::has_category(&category, &self.db_pool).await?;
+            if new_user && !users {
+                report
+                    .skipped
+                    .push(format!("meme {}: user {} missing", id, user));
+                continue;
+            }
+            if new_category && !categories {
+                report
+                    .skipped
+                    .push(format!("meme {}: category {} missing", id, category));
+                continue;
+            }
+            let (cid, size) = match file {
+                MemeFile::Stored(cid) => {
+                    let size = self.storage.size(&cid).await.ok().map(|size| size as i64);
+                    (cid, size)
+                },
+                MemeFile::Media(mxc) => match self.download_media(&mxc).await {
+                    Ok(data) => {
+                        let stored = self.storage.add(data, filename.clone()).await?;
+                        (stored.cid, Some(stored.size))
+                    },
+                    Err(err) => {
+                        report
+                            .skipped
+                            .push(format!("meme {}: downloading {} failed: {}", id, mxc, err));
+                        continue;
+                    },
+                },
+            };
+            if new_user {
+                let name = self
+                    .get_displayname(&event.sender)
+                    .await?
+                    .unwrap_or_else(|| user.clone());
+                sql::add_user(&user, &name, &self.db_pool).await?;
+                report.users += 1;
+            }
+            if new_category {
+                sql::add_category(&category, &self.db_pool).await?;
+                report.categories += 1;
+            }
+
+            let rebuilt = RebuiltMeme {
+                id,
+                filename,
+                user,
+                category,
+                timestamp: event.origin_server_ts / 1000,
+                cid,
+                size,
+            };
+            sql::add_meme(&rebuilt, &self.db_pool).await?;
+            report.memes += 1;
+        }
+
+        reset_meme_sequence(&self.db_pool).await?;
+        self.outbox_notify.notify_one();
+        Ok(report)
+    }
+}
@@ -0,0 +1,76 @@
+use sqlx::{postgres::PgRow, PgPool, Result, Row};
+
+use crate::outbox;
+
+pub struct RebuiltMeme {
+    pub id: i32,
+    pub filename: String,
+    pub user: String,
+    pub category: String,
+    pub timestamp: i64,
+    pub cid: String,
+    pub size: Option<i64>,
+}
+
+pub async fn count_memes(pool: &PgPool) -> Result<i64> {
+    sqlx::query("SELECT COUNT(*) AS count FROM memes")
+        .map(|row: PgRow| row.get("count"))
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn has_user(id: &str, pool: &PgPool) -> Result<bool> {
+    sqlx::query("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) AS found")
+        .bind(id)
+        .map(|row: PgRow| row.get("found"))
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn has_category(id: &str, pool: &PgPool) -> Result<bool> {
+    sqlx::query("SELECT EXISTS(SELECT 1 FROM categories WHERE id = $1) AS found")
+        .bind(id)
+        .map(|row: PgRow| row.get("found"))
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn add_user(id: &str, name: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO users (id, name) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+        .bind(id)
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Adds a category named like its ID after the existing ones.
+pub async fn add_category(id: &str, pool: &PgPool) -> Result<()> {
+    sqlx::query("INSERT INTO categories (num, id, name) SELECT COALESCE(MAX(num), 0) + 1, $1, $1 FROM categories ON CONFLICT DO NOTHING")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Inserts the meme with its original ID and queues pinning its file.
+pub async fn add_meme(meme: &RebuiltMeme, pool: &PgPool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("INSERT INTO memes (id, filename, userid, category, timestamp, ip, cid, size) VALUES ($1, $2, $3, $4, TO_TIMESTAMP($5) AT TIME ZONE 'UTC', 'matrix', $6, $7)")
+        .bind(meme.id)
+        .bind(&meme.filename)
+        .bind(&meme.user)
+        .bind(&meme.category)
+        .bind(meme.timestamp as f64)
+        .bind(&meme.cid)
+        .bind(meme.size)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("INSERT INTO outbox (memeid, action) VALUES ($1, $2)")
+        .bind(meme.id)
+        .bind(outbox::ACTION_PIN)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
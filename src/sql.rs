@@ -1,5 +1,6 @@
-use crate::ipfs::IPFSFile;
 use crate::models::{Category, Count, Meme, MemeOptions, User, UserIdentifier};
+use crate::outbox;
+use crate::storage::StoredFile;
 use crate::JMServiceInner;
 use sqlx::postgres::PgRow;
 use sqlx::{Result, Row};
@@ -120,6 +121,7 @@ impl JMServiceInner {
             UserIdentifier::Id(id) => sqlx::query("SELECT id, name, COALESCE(MD5(token), '0') AS hash, uploads FROM (SELECT id, name, COALESCE(count.uploads, 0) AS uploads FROM users LEFT JOIN (SELECT userid, COUNT(*)::integer AS uploads FROM memes WHERE DATE(timestamp) = CURRENT_DATE GROUP BY (userid)) AS count ON users.id = count.userid) AS users LEFT JOIN token ON users.id = token.uid WHERE users.id = $1").bind(id),
             UserIdentifier::Token(token) => sqlx::query("SELECT id, name, COALESCE(MD5(token), '0') AS hash, uploads FROM (SELECT id, name, COALESCE(count.uploads, 0) AS uploads FROM users LEFT JOIN (SELECT userid, COUNT(*)::integer AS uploads FROM memes WHERE DATE(timestamp) = CURRENT_DATE GROUP BY (userid)) AS count ON users.id = count.userid) AS users LEFT JOIN token ON users.id = token.uid WHERE token = $1").bind(token),
             UserIdentifier::Username(name) => sqlx::query("SELECT id, name, COALESCE(MD5(token), '0') AS hash, uploads FROM (SELECT id, name, COALESCE(count.uploads, 0) AS uploads FROM users LEFT JOIN (SELECT userid, COUNT(*)::integer AS uploads FROM memes WHERE DATE(timestamp) = CURRENT_DATE GROUP BY (userid)) AS count ON users.id = count.userid) AS users LEFT JOIN token ON users.id = token.uid WHERE name = $1").bind(name),
+            UserIdentifier::Matrix(mxid) => sqlx::query("SELECT id, name, COALESCE(MD5(token), '0') AS hash, uploads FROM (SELECT id, name, COALESCE(count.uploads, 0) AS uploads FROM users LEFT JOIN (SELECT userid, COUNT(*)::integer AS uploads FROM memes WHERE DATE(timestamp) = CURRENT_DATE GROUP BY (userid)) AS count ON users.id = count.userid) AS users LEFT JOIN token ON users.id = token.uid WHERE users.id IN (SELECT id FROM users WHERE authsource->>'matrix' = $1)").bind(mxid),
             UserIdentifier::Null => sqlx::query("SELECT id, name, '0' AS hash, 0 AS uploads FROM users WHERE id = '000'"),
         };
         let q: Option<User> = query
@@ -148,31 +150,104 @@ impl JMServiceInner {
         Ok(q)
     }
 
-    pub async fn check_token(&self, token: &String) -> Result<Option<User>> {
-        let user = self.get_user(UserIdentifier::Token(token.clone())).await?;
+    pub async fn check_token(&self, token: &str) -> Result<Option<User>> {
+        let user = self
+            .get_user(UserIdentifier::Token(token.to_string()))
+            .await?;
         Ok(user)
     }
 
-    pub async fn add_meme_sql(
+    /// Sets the name and avatar CID of a user, keeping the ones not given.
+    pub async fn update_profile(
+        &self,
+        user_id: &str,
+        name: Option<&str>,
+        avatar: Option<&str>,
+    ) -> Result<u64> {
+        sqlx::query("UPDATE users SET name = COALESCE($2, name), avatar = COALESCE($3, avatar) WHERE id = $1")
+            .bind(user_id)
+            .bind(name)
+            .bind(avatar)
+            .execute(&self.db_pool)
+            .await
+    }
+
+    /// Inserts all uploaded files together with their outbox entries in one
+    /// transaction. Files the user already uploaded with the same name and
+    /// CID are not inserted again, so retried uploads don't create duplicates.
+    /// Without a timestamp the memes are inserted with the current time.
+    /// `matrix_action` is the outbox action sending the memes to Matrix, if
+    /// it is enabled.
+    pub async fn add_memes_sql(
         &self,
         user: &User,
-        file: &IPFSFile,
-        ip: &String,
+        files: &[StoredFile],
+        ip: &str,
         category: &Category,
-    ) -> Result<i64> {
+        timestamp: Option<i64>,
+        matrix_action: &str,
+    ) -> Result<Vec<i32>> {
         let mut tx = self.db_pool.begin().await?;
-        sqlx::query("INSERT INTO memes (filename, userid, category, timestamp, ip, cid) VALUES ($1, $2, $3, NOW(), $4, $5)")
-        .bind(&file.name)
-        .bind(&user.id)
-        .bind(&category.id)
-        .bind(ip)
-        .bind(&file.hash)
-        .execute(&mut tx).await?;
-        let id: i64 = sqlx::query("SELECT LASTVAL() as id")
+        let mut ids = vec![];
+        for file in files {
+            let existing: Option<i32> = sqlx::query(
+                "SELECT id FROM memes WHERE userid = $1 AND filename = $2 AND cid = $3",
+            )
+            .bind(&user.id)
+            .bind(&file.name)
+            .bind(&file.cid)
             .map(|row: PgRow| row.get("id"))
-            .fetch_one(&mut tx)
+            .fetch_optional(&mut tx)
             .await?;
+            if let Some(id) = existing {
+                ids.push(id);
+                continue;
+            }
+            let id: i32 = sqlx::query("INSERT INTO memes (filename, userid, category, timestamp, ip, cid, size) VALUES ($1, $2, $3, COALESCE(TO_TIMESTAMP($7) AT TIME ZONE 'UTC', NOW()::timestamp), $4, $5, $6) RETURNING id")
+                .bind(&file.name)
+                .bind(&user.id)
+                .bind(&category.id)
+                .bind(ip)
+                .bind(&file.cid)
+                .bind(file.size)
+                .bind(timestamp.map(|timestamp| timestamp as f64))
+                .map(|row: PgRow| row.get("id"))
+                .fetch_one(&mut tx)
+                .await?;
+            for action in &[matrix_action, outbox::ACTION_PIN] {
+                if *action == matrix_action && !self.matrix_enabled() {
+                    continue;
+                }
+                sqlx::query("INSERT INTO outbox (memeid, action) VALUES ($1, $2)")
+                    .bind(id)
+                    .bind(*action)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            ids.push(id);
+        }
         tx.commit().await?;
-        Ok(id)
+        Ok(ids)
+    }
+
+    /// Returns the CID and filename of the thumbnail of a file.
+    pub async fn get_thumbnail(&self, cid: &str) -> Result<Option<(String, String)>> {
+        let q: Option<(String, String)> =
+            sqlx::query("SELECT thumbnail, filename FROM thumbnails WHERE cid = $1")
+                .bind(cid)
+                .map(|row: PgRow| (row.get("thumbnail"), row.get("filename")))
+                .fetch_optional(&self.db_pool)
+                .await?;
+        Ok(q)
+    }
+
+    pub async fn add_thumbnail(&self, cid: &str, thumbnail: &str, filename: &str) -> Result<()> {
+        sqlx::query("INSERT INTO thumbnails (cid, thumbnail, filename) VALUES ($1, $2, $3) ON CONFLICT (cid) DO NOTHING")
+            .bind(cid)
+            .bind(thumbnail)
+            .bind(filename)
+            .execute(&self.db_pool)
+            .await?;
+        Ok(())
     }
 }
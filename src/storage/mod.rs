@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use futures_util::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::{error::ServiceError, export::car, ipfs::IpfsStorage, JMService};
+
+pub use self::{local::LocalStorage, s3::S3Storage};
+
+mod local;
+mod s3;
+
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+pub type ByteStream = BoxStream<'static, Result<Bytes, ServiceError>>;
+
+/// A file that was added to a storage backend.
+pub struct StoredFile {
+    pub cid: String,
+    pub name: String,
+    pub size: i64,
+}
+
+/// Content read from a storage backend.
+pub struct Content {
+    pub length: Option<u64>,
+    pub stream: ByteStream,
+}
+
+/// A backend storing the files of memes, addressed by their content
+/// identifier, which is saved in `memes.cid`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn add(&self, file: Bytes, filename: String) -> Result<StoredFile, ServiceError>;
+
+    async fn cat(&self, cid: &str) -> Result<Content, ServiceError>;
+
+    async fn cat_range(&self, cid: &str, offset: u64, length: u64)
+        -> Result<Content, ServiceError>;
+
+    async fn size(&self, cid: &str) -> Result<u64, ServiceError>;
+
+    /// Makes sure the content is kept permanently.
+    async fn pin(&self, cid: &str) -> Result<(), ServiceError>;
+
+    /// Returns whether the content is kept permanently. Backends without
+    /// garbage collection keep everything they store.
+    async fn is_pinned(&self, cid: &str) -> Result<bool, ServiceError> {
+        match self.size(cid).await {
+            Ok(_) => Ok(true),
+            Err(ServiceError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the blocks of the content's DAG in the CARv1 format, without
+    /// a header. The default works for backends storing every file as a
+    /// single block with a raw CID.
+    async fn car_blocks(&self, cid: &str) -> Result<ByteStream, ServiceError> {
+        let binary = car::parse_cid(cid)
+            .filter(|binary| car::is_raw(binary))
+            .ok_or_else(|| ServiceError::InvalidCid(cid.to_string()))?;
+        let content = self.cat(cid).await?;
+        let length = match content.length {
+            Some(length) => length,
+            None => self.size(cid).await?,
+        };
+        let prefix = Bytes::from(car::block_prefix(&binary, length));
+        Ok(stream::once(async move { Ok(prefix) })
+            .chain(content.stream)
+            .boxed())
+    }
+
+    async fn check_health(&self) {}
+
+    /// Returns a URL the CDN can redirect clients to instead of streaming
+    /// the content itself.
+    fn redirect_url(&self, _cid: &str, _filename: &str) -> Result<Option<Url>, ServiceError> {
+        Ok(None)
+    }
+
+    /// How long clients may cache redirects to the URL of `redirect_url`.
+    fn redirect_max_age(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the IPFS backend, for features that only work with IPFS.
+    fn ipfs(&self) -> Option<&IpfsStorage> {
+        None
+    }
+}
+
+/// Checks the health of the storage backend periodically.
+pub async fn run_health_checks(service: JMService) {
+    loop {
+        service.storage.check_health().await;
+        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+    }
+}
+
+/// Calculates the CIDv1 of the data with the raw codec and a SHA-256
+/// multihash, in its base32 string form.
+pub fn raw_cid(data: &[u8]) -> String {
+    let mut bytes = vec![0x01, 0x55, 0x12, 0x20];
+    bytes.extend_from_slice(&Sha256::digest(data));
+    format!("b{}", base32(&bytes))
+}
+
+fn base32(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut out = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_rfc4648() {
+        let vectors = [
+            ("", ""),
+            ("f", "my"),
+            ("fo", "mzxq"),
+            ("foo", "mzxw6"),
+            ("foob", "mzxw6yq"),
+            ("fooba", "mzxw6ytb"),
+            ("foobar", "mzxw6ytboi"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(base32(data.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn raw_cids() {
+        assert_eq!(
+            raw_cid(b""),
+            "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku"
+        );
+        assert_eq!(
+            raw_cid(b"hello world"),
+            "bafkreifzjut3te2nhyekklss27nh3k72ysco7y32koao5eei66wof36n5e"
+        );
+    }
+}
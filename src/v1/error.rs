@@ -39,6 +39,7 @@ impl IntoResponse for APIError {
             APIError::Unauthorized(err) => ErrorResponse::new(StatusCode::UNAUTHORIZED, Some(err)),
             APIError::Forbidden(err) => ErrorResponse::new(StatusCode::FORBIDDEN, Some(err)),
             APIError::NotFound(err) => ErrorResponse::new(StatusCode::NOT_FOUND, Some(err)),
+            APIError::Conflict(err) => ErrorResponse::new(StatusCode::CONFLICT, Some(err)),
             APIError::Internal(err) => {
                 ErrorResponse::new(StatusCode::INTERNAL_SERVER_ERROR, Some(err))
             },
@@ -72,6 +73,18 @@ impl ServiceError {
             },
             ServiceError::Url(_) => "URL parse error".to_string(),
             ServiceError::InvalidResponse(code) => format!("Invalid response code: {}", code),
+            ServiceError::UnknownAction(action) => format!("Unknown outbox action: {}", action),
+            ServiceError::Io(_) => "IO error".to_string(),
+            ServiceError::NoIpfsNode => "No IPFS node available".to_string(),
+            ServiceError::IpfsNodes(_) => "IPFS nodes failed".to_string(),
+            ServiceError::NotFound(cid) => format!("Content not found: {}", cid),
+            ServiceError::Header(_) => "Invalid header value".to_string(),
+            ServiceError::Sql(_) => "SQL error".to_string(),
+            ServiceError::CheckRunning => "A consistency check is already running".to_string(),
+            ServiceError::InvalidCid(cid) => format!("Invalid CID: {}", cid),
+            ServiceError::MatrixDisabled => "Matrix is disabled".to_string(),
+            ServiceError::NotEmpty(table) => format!("The {} table is not empty", table),
+            ServiceError::TooLarge(size) => format!("File larger than {} bytes", size),
         }
     }
 }
@@ -4,7 +4,7 @@ mod routes;
 
 use async_trait::async_trait;
 use axum::extract::{FromRequest, RequestParts};
-pub use routes::routes;
+pub use routes::{routes, MAX_UPLOAD_SIZE, UPLOAD_LIMIT};
 use serde::de::DeserializeOwned;
 
 use crate::error::APIError;
@@ -1,5 +1,7 @@
-use crate::ipfs::IPFSFile;
-use crate::lib::ExtractIP;
+use crate::extract::{ExtractIP, ExtractToken};
+use crate::models::User;
+use crate::outbox;
+use crate::storage::StoredFile;
 use crate::v1::models::*;
 use crate::JMService;
 
@@ -13,6 +15,10 @@ use hyper::StatusCode;
 use super::Query;
 use crate::error::APIError;
 
+pub const UPLOAD_LIMIT: i32 = 20;
+/// Largest body of an upload request in bytes.
+pub const MAX_UPLOAD_SIZE: u64 = 1024 * 1024 * 1024;
+
 async fn meme(
     Query(params): Query<MemeIDQuery>,
     Extension(service): Extension<JMService>,
@@ -113,29 +119,67 @@ async fn random(
     }))
 }
 
+async fn check_upload_token(service: &JMService, token: &str) -> Result<User, APIError> {
+    let user = service
+        .check_token(token)
+        .await?
+        .ok_or_else(|| APIError::Forbidden("token not existing".to_string()))?;
+
+    if user.dayuploads >= UPLOAD_LIMIT {
+        return Err(APIError::Forbidden("Upload limit reached".to_string()));
+    }
+
+    Ok(user)
+}
+
 async fn upload(
-    ContentLengthLimit(mut form): ContentLengthLimit<Multipart, { 1024 * 1024 * 1024 }>,
+    ContentLengthLimit(mut form): ContentLengthLimit<Multipart, MAX_UPLOAD_SIZE>,
     Extension(service): Extension<JMService>,
     ExtractIP(ip): ExtractIP,
+    header_token: Option<ExtractToken>,
 ) -> Result<impl IntoResponse, APIError> {
     let mut category: Option<String> = None;
     let mut token: Option<String> = None;
-    let mut files: Vec<IPFSFile> = vec![];
+    let mut user: Option<User> = None;
+    let mut files: Vec<StoredFile> = vec![];
+
+    // The token has to be known before any file is read, so that no data of
+    // unauthenticated clients ever reaches the storage.
+    if let Some(ExtractToken(t)) = header_token {
+        user = Some(check_upload_token(&service, &t).await?);
+        token = Some(t);
+    }
 
     while let Some(field) = form.next_field().await? {
         match field.name().ok_or_else(|| {
             APIError::BadRequest("A multipart-form field is missing a name".to_string())
         })? {
-            "token" => token = Some(field.text().await?),
+            "token" => {
+                let t = field.text().await?;
+                if user.is_none() {
+                    user = Some(check_upload_token(&service, &t).await?);
+                    token = Some(t);
+                }
+            },
             "category" => category = Some(field.text().await?),
             "file" | "file[]" => {
+                let user = user.as_ref().ok_or_else(|| {
+                    APIError::Unauthorized(
+                        "Missing token, it must be sent before any file".to_string(),
+                    )
+                })?;
+
+                if user.dayuploads + (files.len() as i32) >= UPLOAD_LIMIT {
+                    return Err(APIError::Forbidden("Upload limit reached".to_string()));
+                }
+
                 let filename = field
                     .file_name()
                     .ok_or_else(|| {
                         APIError::BadRequest("A file field has no filename".to_string())
                     })?
                     .to_string();
-                let file = service.ipfs_add(field.bytes().await?, filename).await?;
+                let file = service.storage.add(field.bytes().await?, filename).await?;
                 files.push(file);
             },
             _ => (),
@@ -143,49 +187,33 @@ async fn upload(
     }
 
     let token = token.ok_or_else(|| APIError::Unauthorized("Missing token".to_string()))?;
+    let user = user.ok_or_else(|| APIError::Unauthorized("Missing token".to_string()))?;
     let category = category.ok_or_else(|| APIError::BadRequest("Missing category".to_string()))?;
-    let user = service
-        .check_token(&token)
-        .await?
-        .ok_or_else(|| APIError::Forbidden("token not existing".to_string()))?;
-    let total = (user.dayuploads as isize) + (files.len() as isize);
-
-    if total > 20 {
-        return Err(APIError::Forbidden("Upload limit reached".to_string()));
-    }
 
     let cat = service
         .get_category(&category)
         .await?
         .ok_or_else(|| APIError::BadRequest("Category not existing".to_string()))?;
 
-    let ip = ip.to_string();
-
-    let mut links: Vec<String> = vec![];
-
-    for f in files {
-        let res = service.add_meme_sql(&user, &f, &ip, &cat).await?;
-
-        if res == 0 {
-            return Err(APIError::Internal("Database insertion error".to_string()));
-        }
-        service
-            .add_meme(
-                cat.id.clone(),
-                f.name.clone(),
-                f.hash.clone(),
-                user.id.clone(),
-                res,
-            )
-            .await?;
-        service.ipfs_pin(f.hash).await?;
-        links.push(format!(
-            "{}/{}/{}",
-            service.ext_cdn_url(),
-            user.id.clone(),
-            f.name.clone()
-        ));
-    }
+    // Matrix and pinning are done by the outbox worker, so the upload
+    // only fails if the database insertion itself fails.
+    service
+        .add_memes_sql(
+            &user,
+            &files,
+            &ip.to_string(),
+            &cat,
+            None,
+            outbox::ACTION_MATRIX,
+        )
+        .await?;
+    service.outbox_notify.notify_one();
+    service.mfs_notify.notify_one();
+
+    let links = files
+        .iter()
+        .map(|f| format!("{}/{}/{}", service.ext_cdn_url(), user.id, f.name))
+        .collect();
 
     Ok((
         StatusCode::CREATED,
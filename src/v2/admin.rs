@@ -0,0 +1,164 @@
+use async_trait::async_trait;
+use axum::{
+    extract::{Extension, FromRequest, Path, Query, RequestParts},
+    handler::{get, post},
+    response::IntoResponse,
+    routing::BoxRoute,
+    Json, Router,
+};
+use hyper::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    Body, HeaderMap, StatusCode,
+};
+use reqwest::header::HeaderValue;
+
+use crate::{
+    error::APIError,
+    export::{self, ExportManifest, CAR_CONTENT_TYPE},
+    extract::ExtractToken,
+    models::MemeOptions,
+    outbox, JMService,
+};
+
+use super::models::{CheckQuery, MemeFilterQuery, OutboxQuery};
+
+/// Guards a route by the token in the `Authorization` header, rejecting every
+/// user that is not listed in the `admins` config option.
+pub struct Admin;
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = APIError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(service) = Extension::<JMService>::from_request(req)
+            .await
+            .map_err(|_| APIError::Internal("Service is missing".to_string()))?;
+        let ExtractToken(token) = ExtractToken::from_request(req)
+            .await
+            .map_err(|err| APIError::Unauthorized(err.to_string()))?;
+        let user = service
+            .check_token(&token)
+            .await?
+            .ok_or_else(|| APIError::Forbidden("token not existing".to_string()))?;
+        if !service.is_admin(&user) {
+            return Err(APIError::Forbidden("Admin permission required".to_string()));
+        }
+        Ok(Self)
+    }
+}
+
+async fn get_outbox(
+    _: Admin,
+    Query(query): Query<OutboxQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    Ok(Json(
+        outbox::sql::get_entries(
+            query.pending.unwrap_or(true),
+            query.limit.unwrap_or(100),
+            &service.db_pool,
+        )
+        .await?,
+    ))
+}
+
+async fn retry_outbox(
+    _: Admin,
+    Path(entry_id): Path<i32>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    if outbox::sql::retry(entry_id, &service.db_pool).await? == 0 {
+        return Err(APIError::NotFound(
+            "Pending outbox entry not found".to_string(),
+        ));
+    }
+    service.outbox_notify.notify_one();
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn get_cache_stats(
+    _: Admin,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    let cache = service
+        .cache
+        .as_ref()
+        .ok_or_else(|| APIError::NotFound("Cache is disabled".to_string()))?;
+    Ok(Json(cache.stats()))
+}
+
+async fn get_check(
+    _: Admin,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    Ok(Json(service.get_latest_check().await?.ok_or_else(
+        || APIError::NotFound("No consistency check found".to_string()),
+    )?))
+}
+
+async fn run_check(
+    _: Admin,
+    Query(query): Query<CheckQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    let running = service
+        .lock_check()
+        .map_err(|_| APIError::Conflict("A consistency check is already running".to_string()))?;
+    tokio::spawn(async move {
+        if let Err(err) = service
+            .run_check(running, query.repair.unwrap_or(false), query.limit)
+            .await
+        {
+            eprintln!("Consistency check error: {}", err);
+        }
+    });
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Filters memes like the meme list, but without a default limit.
+fn export_options(query: MemeFilterQuery) -> MemeOptions {
+    MemeOptions {
+        limit: query.limit,
+        ..query.into()
+    }
+}
+
+async fn export_car(
+    _: Admin,
+    Query(query): Query<MemeFilterQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    let memes = service.get_memes(export_options(query)).await?;
+    let stream = export::car_stream(service.clone(), &memes)?;
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(CAR_CONTENT_TYPE));
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"jensmemes.car\""),
+    );
+    Ok((StatusCode::OK, headers, Body::wrap_stream(stream)))
+}
+
+async fn export_manifest(
+    _: Admin,
+    Query(query): Query<MemeFilterQuery>,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    let memes = service.get_memes(export_options(query)).await?;
+    Ok(Json(ExportManifest::new(memes)))
+}
+
+pub fn routes() -> Router<BoxRoute> {
+    Router::new()
+        .route("/outbox", get(get_outbox))
+        .route("/outbox/:entry_id/retry", post(retry_outbox))
+        .route("/cache", get(get_cache_stats))
+        .route("/check", get(get_check).post(run_check))
+        .route("/export", get(export_car))
+        .route("/export/manifest", get(export_manifest))
+        .boxed()
+}
@@ -1,4 +1,5 @@
-mod models;
+mod admin;
+pub mod models;
 mod routes;
 
 pub use routes::routes;
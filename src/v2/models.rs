@@ -27,16 +27,54 @@ pub struct MemeFilterQuery {
     pub after: Option<i32>,
 }
 
+#[derive(Deserialize)]
+pub struct OutboxQuery {
+    pub pending: Option<bool>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct CheckQuery {
+    pub repair: Option<bool>,
+    pub limit: Option<i64>,
+}
+
 #[derive(Serialize)]
 pub struct CDNEntry {
     pub directories: Vec<String>,
-    pub files: Vec<String>,
+    pub files: Vec<CDNFile>,
+    pub total: i64,
 }
 
 #[derive(Serialize)]
 pub struct CDNFile {
     pub cid: String,
     pub filename: String,
+    pub size: Option<i64>,
+    pub timestamp: i32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CDNSort {
+    Name,
+    Time,
+    Size,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize)]
+pub struct CDNDirQuery {
+    pub sort: Option<CDNSort>,
+    pub order: Option<SortOrder>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
 }
 
 impl From<Meme> for V2Meme {
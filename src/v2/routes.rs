@@ -1,6 +1,6 @@
 use axum::{
-    extract::{Extension, Path, Query},
-    handler::get,
+    extract::{ContentLengthLimit, Extension, Multipart, Path, Query},
+    handler::{get, put},
     response::IntoResponse,
     routing::BoxRoute,
     Json, Router,
@@ -8,11 +8,16 @@ use axum::{
 
 use crate::{
     error::APIError,
+    extract::ExtractToken,
+    media::image_type,
     models::{MemeOptions, UserIdentifier},
     JMService,
 };
 
-use super::models::{MemeFilterQuery, V2Meme, V2User};
+use super::{
+    admin,
+    models::{MemeFilterQuery, V2Meme, V2User},
+};
 
 async fn get_meme(
     Path(meme_id): Path<i32>,
@@ -96,6 +101,67 @@ async fn get_users(
     ))
 }
 
+/// Changes the name or avatar of a user, which can only be done by the user
+/// itself or an admin.
+async fn put_profile(
+    ContentLengthLimit(mut form): ContentLengthLimit<Multipart, { 10 * 1024 * 1024 }>,
+    Path(user_id): Path<String>,
+    ExtractToken(token): ExtractToken,
+    Extension(service): Extension<JMService>,
+) -> Result<impl IntoResponse, APIError> {
+    let user = service
+        .check_token(&token)
+        .await?
+        .ok_or_else(|| APIError::Forbidden("token not existing".to_string()))?;
+    if user.id != user_id && !service.is_admin(&user) {
+        return Err(APIError::Forbidden(
+            "Only the user can change its profile".to_string(),
+        ));
+    }
+    if service
+        .get_user(UserIdentifier::Id(user_id.clone()))
+        .await?
+        .is_none()
+    {
+        return Err(APIError::NotFound("User not found".to_string()));
+    }
+
+    let mut name: Option<String> = None;
+    let mut avatar: Option<String> = None;
+    while let Some(field) = form.next_field().await? {
+        match field.name() {
+            Some("name") => name = Some(field.text().await?),
+            Some("avatar") => {
+                let data = field.bytes().await?;
+                let mime = image_type(&data).ok_or_else(|| {
+                    APIError::BadRequest(
+                        "The avatar must be a PNG, GIF, JPEG or WebP image".to_string(),
+                    )
+                })?;
+                let filename = format!("avatar.{}", mime.trim_start_matches("image/"));
+                let cid = service.storage.add(data, filename).await?.cid;
+                service.pin(&cid).await?;
+                avatar = Some(cid);
+            },
+            _ => (),
+        }
+    }
+    if name.is_none() && avatar.is_none() {
+        return Err(APIError::BadRequest("Missing name or avatar".to_string()));
+    }
+
+    service
+        .update_profile(&user_id, name.as_deref(), avatar.as_deref())
+        .await?;
+    service.profile_notify.notify_one();
+    Ok(Json(V2User::from(
+        service
+            .get_user(UserIdentifier::Id(user_id))
+            .await?
+            .ok_or_else(|| APIError::NotFound("User not found".to_string()))?,
+    )))
+}
+
 async fn get_user_memes(
     Query(filter): Query<MemeFilterQuery>,
     Path(user_id): Path<String>,
@@ -151,6 +217,7 @@ fn user_routes() -> Router<BoxRoute> {
     Router::new()
         .route("/", get(get_users))
         .route("/:user_id", get(get_user))
+        .route("/:user_id/profile", put(put_profile))
         .route("/:user_id/memes", get(get_user_memes))
         .route("/:user_id/memes/:filename", get(get_user_meme))
         .boxed()
@@ -161,5 +228,6 @@ pub fn routes() -> Router<BoxRoute> {
         .nest("/memes", meme_routes())
         .nest("/categories", category_routes())
         .nest("/users", user_routes())
+        .nest("/admin", admin::routes())
         .boxed()
 }
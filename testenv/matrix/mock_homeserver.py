@@ -0,0 +1,59 @@
+#!/usr/bin/env python3
+"""Minimal homeserver serving the room and media in room.json, enough to run
+the `rebuild` command against. Requests that change something are logged and
+answered with an empty success."""
+import base64
+import json
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+from pathlib import Path
+from urllib.parse import unquote, urlparse
+
+ROOM = json.loads((Path(__file__).parent / "room.json").read_text())
+EVENTS = {event["event_id"]: event for event in ROOM["events"] + ROOM["state"]}
+
+
+class Handler(BaseHTTPRequestHandler):
+    def reply(self, status, body):
+        data = json.dumps(body).encode()
+        self.send_response(status)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(data)))
+        self.end_headers()
+        self.wfile.write(data)
+
+    def not_found(self):
+        self.reply(404, {"errcode": "M_NOT_FOUND", "error": "Not found"})
+
+    def do_GET(self):
+        parts = [unquote(part) for part in urlparse(self.path).path.split("/")[4:]]
+        if parts[:2] == ["directory", "room"] and parts[2] in ROOM["aliases"]:
+            self.reply(200, {"room_id": ROOM["room_id"]})
+        elif parts[:2] == ["rooms", ROOM["room_id"]] and parts[2:] == ["state"]:
+            self.reply(200, ROOM["state"])
+        elif parts[:2] == ["rooms", ROOM["room_id"]] and parts[2] == "event":
+            event = EVENTS.get(parts[3])
+            self.reply(200, event) if event else self.not_found()
+        elif parts[0] == "download" and "/".join(parts[1:3]) in ROOM["media"]:
+            data = base64.b64decode(ROOM["media"]["/".join(parts[1:3])])
+            self.send_response(200)
+            self.send_header("Content-Type", "application/octet-stream")
+            self.send_header("Content-Length", str(len(data)))
+            self.end_headers()
+            self.wfile.write(data)
+        elif parts[0] == "profile" and parts[2:] == ["displayname"]:
+            name = ROOM["displaynames"].get(parts[1])
+            self.reply(200, {"displayname": name}) if name else self.not_found()
+        else:
+            self.not_found()
+
+    def do_PUT(self):
+        self.reply(200, {"event_id": "$mock"})
+
+    def do_POST(self):
+        self.reply(200, {"room_id": ROOM["room_id"], "user_id": "@mock:localhost"})
+
+
+if __name__ == "__main__":
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 8008
+    HTTPServer(("127.0.0.1", port), Handler).serve_forever()
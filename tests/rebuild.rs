@@ -0,0 +1,175 @@
+//! Rebuilds the memes from the room in `testenv/matrix/room.json`, served by
+//! `testenv/matrix/mock_homeserver.py`. Needs `python3` and a Postgres 13+
+//! server the test can create a temporary database on, given by
+//! `JMSERVER_TEST_DATABASE` like `postgres://postgres@127.0.0.1:5432/postgres`,
+//! so it only runs with `cargo test -- --ignored`.
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    process::Stdio,
+    time::Duration,
+};
+
+use sqlx::{postgres::PgRow, Executor, PgPool, Row};
+use tokio::process::{Child, Command};
+use url::Url;
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Starts the mock homeserver on a free port, which is stopped when the
+/// returned child is dropped.
+async fn start_homeserver() -> (Child, SocketAddr) {
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let child = Command::new("python3")
+        .arg(manifest_dir().join("testenv/matrix/mock_homeserver.py"))
+        .arg(addr.port().to_string())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("python3 is needed to run the mock homeserver");
+    for _ in 0..50 {
+        if TcpStream::connect(addr).is_ok() {
+            return (child, addr);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("The mock homeserver didn't start");
+}
+
+#[tokio::test]
+#[ignore = "needs python3 and JMSERVER_TEST_DATABASE pointing at a Postgres server"]
+async fn rebuild_from_matrix() {
+    let admin_url = std::env::var("JMSERVER_TEST_DATABASE")
+        .expect("JMSERVER_TEST_DATABASE must point at a Postgres server");
+    let name = format!("jmserver_rebuild_{}", rand::random::<u32>());
+    let admin = PgPool::new(&admin_url).await.unwrap();
+    (&admin)
+        .execute(format!("CREATE DATABASE {}", name).as_str())
+        .await
+        .unwrap();
+    let mut url = Url::parse(&admin_url).unwrap();
+    url.set_path(&name);
+
+    // Run in its own task so the database is dropped even if an assertion fails
+    let result = tokio::spawn(run_rebuild(url.to_string(), name.clone())).await;
+
+    // A failed run leaves its connections open
+    (&admin)
+        .execute(format!("DROP DATABASE {} WITH (FORCE)", name).as_str())
+        .await
+        .unwrap();
+    match result {
+        Ok(result) => result.unwrap(),
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+async fn run_rebuild(database: String, name: String) -> Result<(), String> {
+    let pool = PgPool::new(&database).await.unwrap();
+    let schema = std::fs::read_to_string(manifest_dir().join("schema.sql")).unwrap();
+    (&pool).execute(schema.as_str()).await.unwrap();
+    // The sender of the image posted in the room
+    sqlx::query("INSERT INTO users (id, name, authsource) VALUES ('2', 'someone', '{\"matrix\": \"@someone:localhost\"}')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let (_homeserver, homeserver) = start_homeserver().await;
+    let storage = std::env::temp_dir().join(&name);
+    let config = std::env::temp_dir().join(format!("{}.toml", name));
+    std::fs::write(
+        &config,
+        format!(
+            "addr = \"127.0.0.1:0\"\n\
+             database = \"{}\"\n\
+             int_cdn = \"http://127.0.0.1/cdn\"\n\
+             ext_cdn = \"http://127.0.0.1/cdn\"\n\
+             matrix_url = \"http://{}\"\n\
+             matrix_token = \"as\"\n\
+             matrix_domain = \"localhost\"\n\
+             \n\
+             [storage]\n\
+             type = \"local\"\n\
+             dir = \"{}\"\n",
+            database,
+            homeserver,
+            storage.display()
+        ),
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_jmserver"))
+        .arg("--config")
+        .arg(&config)
+        .args(["rebuild", "--users", "--categories"])
+        .output()
+        .await
+        .unwrap();
+    let _ = std::fs::remove_file(&config);
+    let _ = std::fs::remove_dir_all(&storage);
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+
+    let memes: Vec<(i32, String, String, String, i32)> = sqlx::query("SELECT id, filename, userid, category, UNIX_TIMESTAMP(timestamp) AS ts FROM memes ORDER BY id")
+        .map(|row: PgRow| {
+            (
+                row.get("id"),
+                row.get("filename"),
+                row.get("userid"),
+                row.get("category"),
+                row.get("ts"),
+            )
+        })
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    let expected = vec![
+        (0, "uff.png", "0", "uff", 1627689600),
+        (1, "uff2.png", "1", "cringe", 1627776000),
+        (2, "uff3.png", "2", "uff", 1627862400),
+    ];
+    let expected: Vec<(i32, String, String, String, i32)> = expected
+        .into_iter()
+        .map(|(id, filename, user, category, ts)| {
+            (id, filename.into(), user.into(), category.into(), ts)
+        })
+        .collect();
+    assert_eq!(memes, expected);
+
+    let users: Vec<(String, String)> = sqlx::query("SELECT id, name FROM users ORDER BY id")
+        .map(|row: PgRow| (row.get("id"), row.get("name")))
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        users,
+        vec![
+            ("0".to_string(), "alec".to_string()),
+            ("1".to_string(), "1".to_string()),
+            ("2".to_string(), "someone".to_string()),
+        ]
+    );
+
+    let categories: Vec<String> = sqlx::query("SELECT id FROM categories ORDER BY num")
+        .map(|row: PgRow| row.get("id"))
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(categories, vec!["uff".to_string(), "cringe".to_string()]);
+
+    let pins: i64 = sqlx::query("SELECT COUNT(*) AS count FROM outbox WHERE action = 'pin'")
+        .map(|row: PgRow| row.get("count"))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(pins, 3);
+    pool.close().await;
+    Ok(())
+}